@@ -19,6 +19,7 @@ fn build(token_stream: &mut Peekable<impl Iterator<Item = Token>>, path: Vec<Pat
     match next_token {
         Token::Int(num) => JsonPP::Int(num),
         Token::Float(num) => JsonPP::Float(num),
+        Token::BigInt(num) => JsonPP::BigInt(num),
         Token::Text(txt) => JsonPP::String(txt),
         Token::Ident(ident) if ident == "undefined" => JsonPP::Undefined,
         Token::Ident(ident) if ident == "null" => JsonPP::Null,
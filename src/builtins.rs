@@ -1,353 +1,2159 @@
 use std::process::Command;
+use std::{
+    cell::{Cell, RefCell},
+    cmp::Ordering,
+    collections::{HashMap, VecDeque},
+};
 use std::{fs::File, io::Read};
 
+use base64::prelude::*;
+use bigdecimal::{
+    num_bigint::BigInt, num_traits::Euclid, BigDecimal, FromPrimitive, One, RoundingMode, Signed,
+    ToPrimitive, Zero,
+};
+use md5::Md5;
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use regex::Regex;
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use unicode_normalization::{char::is_combining_mark, UnicodeNormalization};
+use unicode_segmentation::UnicodeSegmentation;
+
 use crate::{
-    evaluation,
+    ast_builder, evaluation, jsonpath,
     jsonpp::{Definition, Dynamic, JsonPP},
     parse_bytes,
-    paths::{make_absolute, ref_chain, PathChunk},
+    paths::{
+        format_path, has_wildcards, make_absolute, ref_chain, relative_json_pointer, slice_indices,
+        PathChunk,
+    },
+    tokenizing,
 };
 
-pub(crate) fn num_cmp(
-    args: Vec<JsonPP>,
-    int_f: fn(i64, i64) -> bool,
-    float_f: fn(f64, f64) -> bool,
-) -> JsonPP {
-    assert_eq!(args.len(), 2);
+// Numeric operands converted to a shared representation
+enum NumPair {
+    Int(i64, i64),
+    BigInt(BigInt, BigInt),
+    Float(f64, f64),
+    Decimal(BigDecimal, BigDecimal),
+}
 
-    let first_arg = args[0].clone();
-    let second_arg = args[1].clone();
+fn num_pair(first: &JsonPP, second: &JsonPP) -> Option<NumPair> {
+    // Ints widen to bigints, those to floats and everything to decimals
+    let rank = |value: &JsonPP| match value {
+        JsonPP::Int(_) => Some(0),
+        JsonPP::BigInt(_) => Some(1),
+        JsonPP::Float(_) => Some(2),
+        JsonPP::Decimal(_) => Some(3),
+        _ => None,
+    };
 
-    JsonPP::Bool(match (first_arg.clone(), second_arg.clone()) {
-        (JsonPP::Int(first), JsonPP::Int(second)) => int_f(first, second),
-        (JsonPP::Float(first), JsonPP::Float(second)) => float_f(first, second),
-        (JsonPP::Float(first), JsonPP::Int(second)) => float_f(first, second as f64),
-        (JsonPP::Int(first), JsonPP::Float(second)) => float_f(first as f64, second),
-        _ => panic!(
-            "Invalid operands to a numeric function, {:?} and {:?}",
-            first_arg, second_arg
-        ),
+    Some(match rank(first)?.max(rank(second)?) {
+        0 => {
+            let (JsonPP::Int(first), JsonPP::Int(second)) = (first, second) else {
+                unreachable!()
+            };
+            NumPair::Int(*first, *second)
+        }
+        1 => NumPair::BigInt(to_bigint(first), to_bigint(second)),
+        2 => NumPair::Float(to_f64(first), to_f64(second)),
+        _ => NumPair::Decimal(to_decimal(first), to_decimal(second)),
     })
 }
 
-fn num_pair_op(
-    int_f: fn(i64, i64) -> i64,
-    float_f: fn(f64, f64) -> f64,
-    first_arg: JsonPP,
-    second_arg: JsonPP,
-) -> JsonPP {
-    match (first_arg.clone(), second_arg.clone()) {
-        (JsonPP::Int(first), JsonPP::Int(second)) => JsonPP::Int(int_f(first, second)),
-        (JsonPP::Float(first), JsonPP::Float(second)) => JsonPP::Float(float_f(first, second)),
-        (JsonPP::Float(first), JsonPP::Int(second)) => JsonPP::Float(float_f(first, second as f64)),
-        (JsonPP::Int(first), JsonPP::Float(second)) => JsonPP::Float(float_f(first as f64, second)),
-        _ => panic!(
+fn to_bigint(value: &JsonPP) -> BigInt {
+    match value {
+        JsonPP::Int(val) => BigInt::from(*val),
+        JsonPP::BigInt(val) => val.clone(),
+        other => panic!("Can't convert {:?} to a big integer", other),
+    }
+}
+
+fn to_f64(value: &JsonPP) -> f64 {
+    match value {
+        JsonPP::Int(val) => *val as f64,
+        JsonPP::Float(val) => *val,
+        JsonPP::BigInt(val) => val.to_f64().unwrap(),
+        JsonPP::Decimal(val) => val.to_f64().unwrap(),
+        other => panic!("Can't convert {:?} to float", other),
+    }
+}
+
+fn to_decimal(value: &JsonPP) -> BigDecimal {
+    match value {
+        JsonPP::Int(val) => BigDecimal::from(*val),
+        JsonPP::BigInt(val) => BigDecimal::from(val.clone()),
+        JsonPP::Float(val) => float_decimal(*val),
+        JsonPP::Decimal(val) => val.clone(),
+        other => panic!("Can't convert {:?} to decimal", other),
+    }
+}
+
+fn float_decimal(val: f64) -> BigDecimal {
+    // Going through the shortest representation makes 0.1 exactly 0.1
+    val.to_string()
+        .parse()
+        .unwrap_or_else(|_| panic!("Can't convert {} to decimal", val))
+}
+
+fn is_zero(value: &JsonPP) -> bool {
+    match value {
+        JsonPP::Int(val) => *val == 0,
+        JsonPP::Float(val) => *val == 0.0,
+        JsonPP::BigInt(val) => val.is_zero(),
+        JsonPP::Decimal(val) => val.is_zero(),
+        _ => false,
+    }
+}
+
+pub(crate) fn num_ordering(first: &JsonPP, second: &JsonPP) -> Option<Ordering> {
+    match num_pair(first, second)? {
+        NumPair::Int(first, second) => Some(first.cmp(&second)),
+        NumPair::BigInt(first, second) => Some(first.cmp(&second)),
+        NumPair::Float(first, second) => first.partial_cmp(&second),
+        NumPair::Decimal(first, second) => Some(first.cmp(&second)),
+    }
+}
+
+pub(crate) fn num_cmp(args: Vec<JsonPP>, cmp_f: fn(Ordering) -> bool) -> JsonPP {
+    assert_eq!(args.len(), 2);
+
+    if num_pair(&args[0], &args[1]).is_none() {
+        panic!(
+            "Invalid operands to a numeric function, {:?} and {:?}",
+            args[0], args[1]
+        );
+    }
+
+    // NaN is not comparable, so all comparisons to it are false
+    JsonPP::Bool(num_ordering(&args[0], &args[1]).is_some_and(cmp_f))
+}
+
+thread_local! {
+    // Whether integer overflow promotes to float instead of exiting
+    static OVERFLOW_TO_FLOAT: Cell<bool> = const { Cell::new(false) };
+}
+
+pub(crate) fn set_overflow_to_float(enabled: bool) {
+    OVERFLOW_TO_FLOAT.set(enabled);
+}
+
+// The same operation for each numeric representation
+struct NumOp {
+    int: fn(i64, i64) -> Option<i64>,
+    big: fn(BigInt, BigInt) -> BigInt,
+    float: fn(f64, f64) -> f64,
+    decimal: fn(BigDecimal, BigDecimal) -> BigDecimal,
+}
+
+fn num_pair_op(op: &NumOp, first_arg: JsonPP, second_arg: JsonPP) -> JsonPP {
+    let Some(pair) = num_pair(&first_arg, &second_arg) else {
+        panic!(
             "Invalid operands to a numeric function, {:?} and {:?}",
             first_arg, second_arg
-        ),
+        );
+    };
+
+    match pair {
+        NumPair::Int(first, second) => match (op.int)(first, second) {
+            Some(result) => JsonPP::Int(result),
+            None if OVERFLOW_TO_FLOAT.get() => JsonPP::Float((op.float)(first as f64, second as f64)),
+            None => panic!(
+                "Integer overflow with operands {} and {}, use --overflow-to-float to get a float instead",
+                first, second
+            ),
+        },
+        NumPair::BigInt(first, second) => JsonPP::from((op.big)(first, second)),
+        NumPair::Float(first, second) => JsonPP::Float((op.float)(first, second)),
+        NumPair::Decimal(first, second) => JsonPP::Decimal((op.decimal)(first, second)),
     }
 }
 
-fn num_reduce(
-    int_f: fn(i64, i64) -> i64,
-    float_f: fn(f64, f64) -> f64,
-    args: Vec<JsonPP>,
-) -> JsonPP {
+fn num_reduce(op: NumOp, args: Vec<JsonPP>) -> JsonPP {
     args.into_iter()
-        .reduce(|acc, next| num_pair_op(int_f, float_f, acc, next))
+        .reduce(|acc, next| num_pair_op(&op, acc, next))
         .unwrap()
 }
 
+fn num_list(args: Vec<JsonPP>) -> Vec<JsonPP> {
+    // A single array argument works the same as passing its elements
+    match <[JsonPP; 1]>::try_from(args) {
+        Ok([JsonPP::Array(vec)]) => vec,
+        Ok([other]) => vec![other],
+        Err(args) => args,
+    }
+}
+
+const SUM: NumOp = NumOp {
+    int: i64::checked_add,
+    big: |a, b| a + b,
+    float: |a, b| a + b,
+    decimal: |a, b| a + b,
+};
+
 pub(crate) fn sum_impl(args: Vec<JsonPP>) -> JsonPP {
-    num_reduce(|a, b| a + b, |a, b| a + b, args)
+    let numbers = num_list(args);
+    if numbers.is_empty() {
+        return JsonPP::Int(0);
+    }
+    num_reduce(SUM, numbers)
 }
 
+const MUL: NumOp = NumOp {
+    int: i64::checked_mul,
+    big: |a, b| a * b,
+    float: |a, b| a * b,
+    decimal: |a, b| a * b,
+};
+
 pub(crate) fn mul_impl(args: Vec<JsonPP>) -> JsonPP {
-    num_reduce(|a, b| a * b, |a, b| a * b, args)
+    let numbers = num_list(args);
+    if numbers.is_empty() {
+        return JsonPP::Int(1);
+    }
+    num_reduce(MUL, numbers)
 }
 
+const SUB: NumOp = NumOp {
+    int: i64::checked_sub,
+    big: |a, b| a - b,
+    float: |a, b| a - b,
+    decimal: |a, b| a - b,
+};
+
 pub(crate) fn sub_impl(args: Vec<JsonPP>) -> JsonPP {
     assert_eq!(args.len(), 2);
-    num_reduce(|a, b| a - b, |a, b| a - b, args)
+    num_reduce(SUB, args)
 }
 
+const DIV: NumOp = NumOp {
+    int: i64::checked_div,
+    big: |a, b| a / b,
+    float: |a, b| a / b,
+    decimal: |a, b| a / b,
+};
+
 pub(crate) fn div_impl(args: Vec<JsonPP>) -> JsonPP {
     assert_eq!(args.len(), 2);
-    if matches!(args[1], JsonPP::Float(0.0) | JsonPP::Int(0)) {
+    if is_zero(&args[1]) {
         dbg!("(div {:?})", args);
         panic!("Division by zero");
     }
-    num_reduce(|a, b| a / b, |a, b| a / b, args)
+    num_reduce(DIV, args)
 }
 
-pub(crate) fn mod_impl(args: Vec<JsonPP>) -> JsonPP {
-    assert_eq!(args.len(), 2);
-    num_reduce(|a, b| a % b, |a, b| a % b, args)
+fn decimal_div_euclid(a: BigDecimal, b: BigDecimal) -> BigDecimal {
+    // Rounding the quotient towards negative infinity for positive divisors and
+    // towards positive infinity for negative ones keeps the remainder positive
+    let mode = if b.is_negative() {
+        RoundingMode::Ceiling
+    } else {
+        RoundingMode::Floor
+    };
+    (a / b).with_scale_round(0, mode)
 }
 
-pub(crate) fn pow_impl(args: Vec<JsonPP>) -> JsonPP {
+pub(crate) fn idiv_impl(args: Vec<JsonPP>) -> JsonPP {
     assert_eq!(args.len(), 2);
+    if is_zero(&args[1]) {
+        panic!("Division by zero: {:?}", args);
+    }
     num_reduce(
-        |a, b| {
-            if b.is_positive() {
-                a.pow(b as u32)
-            } else {
-                (a as f64).powf(b as f64).round() as i64
-            }
+        NumOp {
+            int: i64::checked_div_euclid,
+            big: |a, b| Euclid::div_euclid(&a, &b),
+            float: f64::div_euclid,
+            decimal: decimal_div_euclid,
         },
-        f64::powf,
         args,
     )
 }
 
-pub(crate) fn log_impl(args: Vec<JsonPP>) -> JsonPP {
+pub(crate) fn fdiv_impl(mut args: Vec<JsonPP>) -> JsonPP {
+    assert_eq!(args.len(), 2);
+    if is_zero(&args[1]) {
+        panic!("Division by zero: {:?}", args);
+    }
+    // Division of a float never truncates, decimals stay exact
+    if let JsonPP::Int(_) | JsonPP::BigInt(_) = args[0] {
+        args[0] = JsonPP::Float(to_f64(&args[0]));
+    }
+    num_reduce(DIV, args)
+}
+
+pub(crate) fn mod_impl(args: Vec<JsonPP>) -> JsonPP {
     assert_eq!(args.len(), 2);
+    if !matches!(args[1], JsonPP::Float(_)) && is_zero(&args[1]) {
+        panic!("Modulo by zero: {:?}", args);
+    }
     num_reduce(
-        |a, b| b.ilog(a) as i64,
-        |a, b| {
-            if a == 1.0 {
-                panic!("There is no base 1 logarithm")
-            } else {
-                b.log(a)
-            }
+        NumOp {
+            int: i64::checked_rem_euclid,
+            big: |a, b| Euclid::rem_euclid(&a, &b),
+            float: f64::rem_euclid,
+            decimal: |a, b| {
+                let quotient = decimal_div_euclid(a.clone(), b.clone());
+                a - b * quotient
+            },
         },
         args,
     )
 }
 
-pub(crate) fn len_impl(args: Vec<JsonPP>) -> JsonPP {
-    assert_eq!(args.len(), 1);
+pub(crate) fn pow_impl(args: Vec<JsonPP>) -> JsonPP {
+    assert_eq!(args.len(), 2);
+    num_reduce(
+        NumOp {
+            int: |a, b| {
+                if b.is_positive() {
+                    a.checked_pow(u32::try_from(b).ok()?)
+                } else {
+                    // Only a zero base can blow up, 0^-1 is a division by zero
+                    let float = (a as f64).powf(b as f64).round();
+                    (float.is_finite() && float.abs() <= i64::MAX as f64).then_some(float as i64)
+                }
+            },
+            big: |a, b| match b.to_u32() {
+                Some(exponent) => a.pow(exponent),
+                None if b.is_negative() => {
+                    let float = a.to_f64().unwrap().powf(b.to_f64().unwrap());
+                    BigInt::from_f64(float.round())
+                        .unwrap_or_else(|| panic!("Division by zero: {} to the power of {}", a, b))
+                }
+                None => panic!("Exponent {} is too large", b),
+            },
+            float: f64::powf,
+            decimal: |a, b| match b.is_integer().then(|| b.to_i64()).flatten() {
+                Some(exponent) => a.powi(exponent),
+                None => float_decimal(a.to_f64().unwrap().powf(b.to_f64().unwrap())),
+            },
+        },
+        args,
+    )
+}
 
-    JsonPP::Int(match &args[0] {
-        JsonPP::String(inner) => inner.len() as i64,
-        JsonPP::Array(inner) => inner.len() as i64,
-        JsonPP::Object(inner) => inner.len() as i64,
-        _ => panic!("Trying to get the length of something odd"),
-    })
+fn float_log(base: f64, value: f64) -> f64 {
+    if base == 1.0 {
+        panic!("There is no base 1 logarithm")
+    }
+    value.log(base)
 }
 
-pub(crate) fn ref_impl(args: Vec<JsonPP>, self_path: &[PathChunk], root: &JsonPP) -> JsonPP {
-    let JsonPP::String(target) = args[0].clone() else {
-        panic!("Non-string reference: {:?}", args);
-    };
+pub(crate) fn log_impl(args: Vec<JsonPP>) -> JsonPP {
+    assert_eq!(args.len(), 2);
 
-    let target_path = ref_chain(target);
+    match num_pair(&args[0], &args[1]) {
+        Some(NumPair::Int(..) | NumPair::BigInt(..)) => {
+            let (base, value) = (to_bigint(&args[0]), to_bigint(&args[1]));
+            if base <= BigInt::one() || !value.is_positive() {
+                panic!("Invalid logarithm of {} with base {}", value, base);
+            }
 
-    evaluation::abs_fetch(&make_absolute(self_path, &target_path), root)
-        .cloned()
-        .unwrap()
+            // Exact logarithms of integers stay integers, the rest are floats
+            let mut power = BigInt::one();
+            let mut exponent = 0;
+            while power < value {
+                power *= &base;
+                exponent += 1;
+            }
+            if power == value {
+                JsonPP::Int(exponent)
+            } else {
+                JsonPP::Float(float_log(to_f64(&args[0]), to_f64(&args[1])))
+            }
+        }
+        Some(NumPair::Float(base, value)) => JsonPP::Float(float_log(base, value)),
+        Some(NumPair::Decimal(base, value)) => JsonPP::Decimal(float_decimal(float_log(
+            base.to_f64().unwrap(),
+            value.to_f64().unwrap(),
+        ))),
+        None => panic!(
+            "Invalid operands to a numeric function, {:?} and {:?}",
+            args[0], args[1]
+        ),
+    }
 }
 
-pub(crate) fn min_impl(args: Vec<JsonPP>) -> JsonPP {
-    num_reduce(i64::min, f64::min, args)
+fn num_arg(fun: &str, args: &[JsonPP], index: usize) -> JsonPP {
+    let (JsonPP::Int(_) | JsonPP::BigInt(_) | JsonPP::Float(_) | JsonPP::Decimal(_)) = &args[index]
+    else {
+        panic!(
+            "'{}' expects a number as argument {}, found {:?}",
+            fun, index, args[index]
+        );
+    };
+
+    args[index].clone()
 }
 
-pub(crate) fn max_impl(args: Vec<JsonPP>) -> JsonPP {
-    num_reduce(i64::max, f64::max, args)
+pub(crate) fn abs_impl(args: Vec<JsonPP>) -> JsonPP {
+    assert_eq!(args.len(), 1);
+
+    match num_arg("abs", &args, 0) {
+        JsonPP::Float(val) => JsonPP::Float(val.abs()),
+        negative if num_ordering(&negative, &JsonPP::Int(0)) == Some(Ordering::Less) => {
+            num_pair_op(&SUB, JsonPP::Int(0), negative)
+        }
+        other => other,
+    }
 }
 
-pub(crate) fn not_impl(args: Vec<JsonPP>) -> JsonPP {
+pub(crate) fn sign_impl(args: Vec<JsonPP>) -> JsonPP {
     assert_eq!(args.len(), 1);
 
-    JsonPP::Bool(!args[0].is_truthy())
+    let value = num_arg("sign", &args, 0);
+    match num_ordering(&value, &JsonPP::Int(0)) {
+        Some(Ordering::Less) => JsonPP::Int(-1),
+        Some(Ordering::Equal) => JsonPP::Int(0),
+        Some(Ordering::Greater) => JsonPP::Int(1),
+        None => panic!("'sign' is not defined for {:?}", value),
+    }
 }
 
-pub(crate) fn eq_impl(args: Vec<JsonPP>) -> JsonPP {
-    assert_eq!(args.len(), 2);
+fn round_with(fun: &str, args: Vec<JsonPP>, mode: RoundingMode) -> JsonPP {
+    assert!(
+        matches!(args.len(), 1 | 2),
+        "'{}' expects a number and optionally the number of digits",
+        fun
+    );
+
+    let value = num_arg(fun, &args, 0);
+    let digits = if args.len() == 2 {
+        int_arg(fun, &args, 1)
+    } else {
+        0
+    };
 
-    let first_arg = args[0].clone();
-    let second_arg = args[1].clone();
+    // Rounding in decimal avoids 2.675 rounding to 2.67 because of float errors
+    let rounded = to_decimal(&value).with_scale_round(digits, mode);
+    match value {
+        JsonPP::Float(_) if args.len() == 2 => JsonPP::Float(rounded.to_f64().unwrap()),
+        JsonPP::Decimal(_) if args.len() == 2 => JsonPP::Decimal(rounded),
+        _ => JsonPP::from(decimal_to_bigint(rounded)),
+    }
+}
 
-    JsonPP::Bool(first_arg == second_arg)
+pub(crate) fn round_impl(args: Vec<JsonPP>) -> JsonPP {
+    round_with("round", args, RoundingMode::HalfUp)
 }
 
-pub(crate) fn if_impl(args: Vec<JsonPP>) -> JsonPP {
-    assert_eq!(args.len(), 3); // Condition, if true, if not;
+pub(crate) fn floor_impl(args: Vec<JsonPP>) -> JsonPP {
+    round_with("floor", args, RoundingMode::Floor)
+}
 
-    let index = if args[0].is_truthy() { 1 } else { 2 };
-    args[index].clone()
+pub(crate) fn ceil_impl(args: Vec<JsonPP>) -> JsonPP {
+    round_with("ceil", args, RoundingMode::Ceiling)
 }
 
-pub(crate) fn include_impl(args: Vec<JsonPP>) -> JsonPP {
+pub(crate) fn trunc_impl(args: Vec<JsonPP>) -> JsonPP {
+    round_with("trunc", args, RoundingMode::Down)
+}
+
+pub(crate) fn float_fn_impl(fun: &str, args: Vec<JsonPP>, float_f: fn(f64) -> f64) -> JsonPP {
     assert_eq!(args.len(), 1);
 
-    let JsonPP::String(path) = args[0].clone() else {
-        panic!("Include path is not a string")
-    };
+    let input = to_f64(&num_arg(fun, &args, 0));
+    let result = float_f(input);
+    if !result.is_finite() {
+        panic!("'{}' is not defined for {}", fun, input);
+    }
+    JsonPP::Float(result)
+}
 
-    let mut file = File::open(path).unwrap();
-    let mut buffer = vec![];
-    file.read_to_end(&mut buffer).unwrap();
+pub(crate) fn atan2_impl(args: Vec<JsonPP>) -> JsonPP {
+    assert_eq!(args.len(), 2);
 
-    let string: String = str::from_utf8(&buffer)
-        .expect("File to contain utf-8")
-        .to_owned();
-    JsonPP::String(string.trim().to_owned())
+    let y = to_f64(&num_arg("atan2", &args, 0));
+    let x = to_f64(&num_arg("atan2", &args, 1));
+    JsonPP::Float(y.atan2(x))
 }
 
-pub(crate) fn import_impl(args: Vec<JsonPP>) -> JsonPP {
-    assert_eq!(args.len(), 1);
+pub(crate) fn clamp_impl(args: Vec<JsonPP>) -> JsonPP {
+    assert_eq!(args.len(), 3);
+
+    let value = num_arg("clamp", &args, 0);
+    let low = num_arg("clamp", &args, 1);
+    let high = num_arg("clamp", &args, 2);
+    if num_ordering(&low, &high) == Some(Ordering::Greater) {
+        panic!(
+            "'clamp' lower bound {:?} is above the upper bound {:?}",
+            low, high
+        );
+    }
 
-    let JsonPP::String(path) = args[0].clone() else {
-        panic!("Import path is not a string")
-    };
+    if num_ordering(&value, &low) == Some(Ordering::Less) {
+        low
+    } else if num_ordering(&value, &high) == Some(Ordering::Greater) {
+        high
+    } else {
+        value
+    }
+}
 
-    let mut file = File::open(path).unwrap();
-    let mut buffer = vec![];
-    file.read_to_end(&mut buffer).unwrap();
+pub(crate) fn constant_impl(fun: &str, args: Vec<JsonPP>, value: f64) -> JsonPP {
+    assert!(args.is_empty(), "'{}' takes no arguments", fun);
+    JsonPP::Float(value)
+}
 
-    parse_bytes(buffer)
+fn integer_args(fun: &str, args: &[JsonPP]) {
+    for (index, arg) in args.iter().enumerate() {
+        if !matches!(arg, JsonPP::Int(_) | JsonPP::BigInt(_)) {
+            panic!(
+                "'{}' expects an int as argument {}, found {:?}",
+                fun, index, arg
+            );
+        }
+    }
 }
 
-pub(crate) fn str_impl(args: Vec<JsonPP>) -> JsonPP {
-    assert_eq!(args.len(), 1);
+fn bitwise_reduce(fun: &str, op: NumOp, args: Vec<JsonPP>) -> JsonPP {
+    let numbers = num_list(args);
+    assert!(numbers.len() >= 2, "'{}' needs at least two ints", fun);
+    integer_args(fun, &numbers);
+    num_reduce(op, numbers)
+}
 
-    JsonPP::String(match args[0].clone() {
-        JsonPP::String(val) => val,
+pub(crate) fn band_impl(args: Vec<JsonPP>) -> JsonPP {
+    bitwise_reduce(
+        "band",
+        NumOp {
+            int: |a, b| Some(a & b),
+            big: |a, b| a & b,
+            float: |_, _| unreachable!(),
+            decimal: |_, _| unreachable!(),
+        },
+        args,
+    )
+}
 
-        JsonPP::Null => "null".to_owned(),
-        JsonPP::Bool(val) => val.to_string(),
-        JsonPP::Int(val) => val.to_string(),
-        JsonPP::Float(val) => val.to_string(),
+pub(crate) fn bor_impl(args: Vec<JsonPP>) -> JsonPP {
+    bitwise_reduce(
+        "bor",
+        NumOp {
+            int: |a, b| Some(a | b),
+            big: |a, b| a | b,
+            float: |_, _| unreachable!(),
+            decimal: |_, _| unreachable!(),
+        },
+        args,
+    )
+}
 
-        JsonPP::Array(vec) => {
-            format!(
-                "[{}]",
-                vec.into_iter()
-                    .map(|elem| {
-                        let JsonPP::String(val) = str_impl(vec![elem]) else {
-                            panic!("Array element didn't convert to string")
-                        };
-                        val
-                    })
-                    .collect::<Vec<String>>()
-                    .join(", ")
-            )
-        }
-        JsonPP::Object(hash_map) => format!(
-            "{{{}}}",
-            hash_map
-                .into_iter()
-                .map(|(key, elem)| {
-                    let JsonPP::String(val) = str_impl(vec![elem]) else {
-                        panic!("Array element didn't convert to string")
-                    };
-                    format!("\"{}\": {}", key, val)
-                })
-                .collect::<Vec<String>>()
-                .join(", ")
-        ),
-        other => panic!("Can't convert {:?} to string", other),
-    })
+pub(crate) fn bxor_impl(args: Vec<JsonPP>) -> JsonPP {
+    bitwise_reduce(
+        "bxor",
+        NumOp {
+            int: |a, b| Some(a ^ b),
+            big: |a, b| a ^ b,
+            float: |_, _| unreachable!(),
+            decimal: |_, _| unreachable!(),
+        },
+        args,
+    )
 }
 
-pub(crate) fn int_impl(args: Vec<JsonPP>) -> JsonPP {
+pub(crate) fn bnot_impl(args: Vec<JsonPP>) -> JsonPP {
     assert_eq!(args.len(), 1);
+    integer_args("bnot", &args);
 
-    JsonPP::Int(match args[0].clone() {
-        JsonPP::Int(val) => val,
+    // Two's complement, like in most languages (bnot 0) is -1
+    match &args[0] {
+        JsonPP::Int(val) => JsonPP::Int(!val),
+        other => JsonPP::from(!to_bigint(other)),
+    }
+}
 
-        JsonPP::Null => 0,
-        JsonPP::Bool(val) => val as i64,
-        JsonPP::Float(val) => val.round() as i64,
-        JsonPP::String(val) => val
-            .parse::<f64>()
-            .unwrap_or_else(|_| panic!("str to int parse failed: '{}'", val))
-            .round() as i64,
-        other => panic!("Can't convert \"{:?}\" to int", other),
-    })
+fn shift_args(fun: &str, args: &[JsonPP]) {
+    assert_eq!(args.len(), 2);
+    integer_args(fun, args);
+    if to_bigint(&args[1]).is_negative() {
+        panic!("'{}' can't shift by a negative amount, {:?}", fun, args[1]);
+    }
 }
 
-pub(crate) fn float_impl(args: Vec<JsonPP>) -> JsonPP {
-    assert_eq!(args.len(), 1);
+pub(crate) fn shl_impl(args: Vec<JsonPP>) -> JsonPP {
+    shift_args("shl", &args);
+    num_reduce(
+        NumOp {
+            int: |a, b| {
+                if a == 0 {
+                    return Some(0);
+                }
+                let shift = u32::try_from(b).ok()?;
+                // Shifting bits out of an i64 is an overflow
+                let shifted = a.checked_shl(shift)?;
+                (shifted >> shift == a).then_some(shifted)
+            },
+            big: |a, b| a << b.to_usize().expect("Shift is too large"),
+            float: |a, b| a * 2.0f64.powf(b),
+            decimal: |_, _| unreachable!(),
+        },
+        args,
+    )
+}
 
-    JsonPP::Float(match args[0].clone() {
-        JsonPP::Float(val) => val,
+pub(crate) fn shr_impl(args: Vec<JsonPP>) -> JsonPP {
+    shift_args("shr", &args);
+    num_reduce(
+        NumOp {
+            // Arithmetic shift, negative numbers stay negative
+            int: |a, b| Some(a >> b.min(63)),
+            big: |a, b| a >> b.to_usize().unwrap_or(usize::MAX),
+            float: |_, _| unreachable!(),
+            decimal: |_, _| unreachable!(),
+        },
+        args,
+    )
+}
 
-        JsonPP::Null => 0.0,
-        JsonPP::Bool(val) => val as i64 as f64,
-        JsonPP::Int(val) => val as f64,
-        JsonPP::String(val) => val.parse().expect("str to float parse failed"),
-        other => panic!("Can't convert \"{:?}\" to float", other),
-    })
+fn radix_arg(fun: &str, args: &[JsonPP], index: usize) -> u32 {
+    let radix = int_arg(fun, args, index);
+    match u32::try_from(radix) {
+        Ok(radix @ 2..=36) => radix,
+        _ => panic!(
+            "'{}' expects a radix between 2 and 36, found {}",
+            fun, radix
+        ),
+    }
 }
 
-pub(crate) fn range_impl(args: Vec<JsonPP>) -> JsonPP {
-    assert_eq!(args.len(), 2);
+pub(crate) fn parse_int_impl(args: Vec<JsonPP>) -> JsonPP {
+    assert!(args.len() == 1 || args.len() == 2);
+    let string = string_arg("parse_int", &args, 0);
 
-    let JsonPP::Int(start) = args[0].clone() else {
-        panic!("Range start is not an int")
+    let (negative, unsigned) = match string.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, string.strip_prefix('+').unwrap_or(&string)),
+    };
+    let prefixed = |prefix: &str| {
+        unsigned
+            .get(..2)
+            .filter(|start| start.eq_ignore_ascii_case(prefix) && unsigned.len() > 2)
+            .map(|_| &unsigned[2..])
+    };
+    let prefixes = [("0x", 16), ("0o", 8), ("0b", 2)];
+
+    // Without a radix, it is read from the prefix, defaulting to decimal
+    let (radix, digits) = if args.len() == 2 {
+        let radix = radix_arg("parse_int", &args, 1);
+        let prefix = prefixes
+            .iter()
+            .find(|(_, prefix_radix)| *prefix_radix == radix);
+        match prefix.and_then(|(prefix, _)| prefixed(prefix)) {
+            Some(digits) => (radix, digits),
+            None => (radix, unsigned),
+        }
+    } else {
+        prefixes
+            .iter()
+            .find_map(|(prefix, radix)| prefixed(prefix).map(|digits| (*radix, digits)))
+            .unwrap_or((10, unsigned))
+    };
+
+    if digits.is_empty() || !digits.chars().all(|ch| ch.is_digit(radix)) {
+        panic!("'parse_int' can't parse '{}' in base {}", string, radix);
+    }
+
+    let magnitude = BigInt::parse_bytes(digits.as_bytes(), radix).unwrap();
+    JsonPP::from(if negative { -magnitude } else { magnitude })
+}
+
+pub(crate) fn to_radix_impl(args: Vec<JsonPP>) -> JsonPP {
+    assert_eq!(args.len(), 2);
+    integer_args("to_radix", &args[..1]);
+    let radix = radix_arg("to_radix", &args, 1);
+
+    JsonPP::String(to_bigint(&args[0]).to_str_radix(radix))
+}
+
+pub(crate) fn len_impl(args: Vec<JsonPP>) -> JsonPP {
+    assert_eq!(args.len(), 1);
+
+    JsonPP::Int(match &args[0] {
+        JsonPP::String(inner) => inner.chars().count() as i64,
+        JsonPP::Array(inner) => inner.len() as i64,
+        JsonPP::Object(inner) => inner.len() as i64,
+        _ => panic!("Trying to get the length of something odd"),
+    })
+}
+
+pub(crate) fn ref_impl(args: Vec<JsonPP>, self_path: &[PathChunk], root: &JsonPP) -> JsonPP {
+    let JsonPP::String(target) = args[0].clone() else {
+        panic!("Non-string reference: {:?}", args);
+    };
+
+    fetch_ref(&target, self_path, root).unwrap()
+}
+
+pub(crate) fn ref_or_impl(args: Vec<JsonPP>, self_path: &[PathChunk], root: &JsonPP) -> JsonPP {
+    assert_eq!(args.len(), 2);
+
+    let JsonPP::String(target) = args[0].clone() else {
+        panic!("Non-string reference: {:?}", args);
+    };
+
+    fetch_ref(&target, self_path, root).unwrap_or_else(|| args[1].clone())
+}
+
+fn fetch_ref(target: &str, self_path: &[PathChunk], root: &JsonPP) -> Option<JsonPP> {
+    let target_path = make_absolute(self_path, &ref_chain(target.to_owned()));
+
+    if let Some((_, "#")) = relative_json_pointer(target) {
+        // Relative JSON Pointer asking for the key or index instead of the value
+        return Some(match target_path.last() {
+            Some(PathChunk::Key(key)) => JsonPP::String(key.to_owned()),
+            Some(PathChunk::Index(index) | PathChunk::Argument(index)) => {
+                JsonPP::Int(*index as i64)
+            }
+            _ => panic!("Root has no name: '{}'", target),
+        });
+    }
+
+    if has_wildcards(&target_path) {
+        return Some(JsonPP::Array(
+            evaluation::abs_fetch_all(&target_path, root, Some(self_path))
+                .into_iter()
+                .cloned()
+                .collect(),
+        ));
+    }
+
+    evaluation::abs_fetch(&target_path, root).cloned()
+}
+
+pub(crate) fn get_impl(args: Vec<JsonPP>) -> JsonPP {
+    assert!(args.len() == 2 || args.len() == 3);
+    let default = args.get(2).cloned().unwrap_or(JsonPP::Null);
+
+    match (&args[0], &args[1]) {
+        (JsonPP::Object(obj), JsonPP::String(key)) => obj.get(key).cloned(),
+        (JsonPP::Array(arr), JsonPP::Int(index)) => {
+            let absolute = if *index < 0 {
+                arr.len() as i64 + index
+            } else {
+                *index
+            };
+            usize::try_from(absolute)
+                .ok()
+                .and_then(|absolute| arr.get(absolute))
+                .cloned()
+        }
+        // With a default, the wrong kind of key is just another missing one
+        _ if args.len() == 3 => None,
+        (container, key) => panic!("Can't get {:?} from {:?}", key, container),
+    }
+    .unwrap_or(default)
+}
+
+pub(crate) fn get_in_impl(args: Vec<JsonPP>) -> JsonPP {
+    assert!(args.len() == 2 || args.len() == 3);
+    let default = args.get(2).cloned().unwrap_or(JsonPP::Null);
+
+    fetch_in(&args[0], &args[1]).unwrap_or(default)
+}
+
+pub(crate) fn has_impl(args: Vec<JsonPP>) -> JsonPP {
+    assert_eq!(args.len(), 2);
+
+    JsonPP::Bool(match fetch_in(&args[0], &args[1]) {
+        Some(JsonPP::Array(matches)) if has_wildcards(&path_in(&args[1])) => !matches.is_empty(),
+        other => other.is_some(),
+    })
+}
+
+fn path_in(path: &JsonPP) -> Vec<PathChunk> {
+    let JsonPP::String(path) = path else {
+        panic!("Non-string path: {:?}", path);
+    };
+
+    // The value is the root, so relative paths are relative to it as well
+    make_absolute(&[], &ref_chain(path.to_owned()))
+}
+
+fn fetch_in(value: &JsonPP, path: &JsonPP) -> Option<JsonPP> {
+    let path = path_in(path);
+
+    if has_wildcards(&path) {
+        return Some(JsonPP::Array(
+            evaluation::abs_fetch_all(&path, value, None)
+                .into_iter()
+                .cloned()
+                .collect(),
+        ));
+    }
+
+    evaluation::abs_fetch(&path, value).cloned()
+}
+
+pub(crate) fn query_impl(args: Vec<JsonPP>) -> JsonPP {
+    assert_eq!(args.len(), 2);
+
+    let JsonPP::String(path) = args[0].clone() else {
+        panic!("JSONPath query is not a string: {:?}", args[0]);
+    };
+
+    JsonPP::Array(
+        jsonpath::query(&path, &args[1])
+            .into_iter()
+            .cloned()
+            .collect(),
+    )
+}
+
+pub(crate) fn min_impl(args: Vec<JsonPP>) -> JsonPP {
+    let numbers = num_list(args);
+    assert!(!numbers.is_empty(), "'min' needs at least one number");
+    num_reduce(
+        NumOp {
+            int: |a, b| Some(a.min(b)),
+            big: BigInt::min,
+            float: f64::min,
+            decimal: BigDecimal::min,
+        },
+        numbers,
+    )
+}
+
+pub(crate) fn max_impl(args: Vec<JsonPP>) -> JsonPP {
+    let numbers = num_list(args);
+    assert!(!numbers.is_empty(), "'max' needs at least one number");
+    num_reduce(
+        NumOp {
+            int: |a, b| Some(a.max(b)),
+            big: BigInt::max,
+            float: f64::max,
+            decimal: BigDecimal::max,
+        },
+        numbers,
+    )
+}
+
+fn stat_numbers(fun: &str, args: Vec<JsonPP>) -> Vec<JsonPP> {
+    let numbers = num_list(args);
+    if numbers.is_empty() {
+        panic!("'{}' needs at least one number", fun);
+    }
+    for (index, number) in numbers.iter().enumerate() {
+        if num_ordering(number, number).is_none() {
+            panic!("'{}' expects numbers, found {:?} at {}", fun, number, index);
+        }
+    }
+    numbers
+}
+
+fn sorted_numbers(fun: &str, args: Vec<JsonPP>) -> Vec<JsonPP> {
+    let mut numbers = stat_numbers(fun, args);
+    numbers.sort_by(|a, b| num_ordering(a, b).unwrap_or(Ordering::Equal));
+    numbers
+}
+
+fn mean(numbers: Vec<JsonPP>) -> JsonPP {
+    // Dividing by a float makes the mean a float, unless the numbers are decimals
+    let count = JsonPP::Float(numbers.len() as f64);
+    num_pair_op(&DIV, num_reduce(SUM, numbers), count)
+}
+
+pub(crate) fn avg_impl(args: Vec<JsonPP>) -> JsonPP {
+    mean(stat_numbers("avg", args))
+}
+
+pub(crate) fn median_impl(args: Vec<JsonPP>) -> JsonPP {
+    let numbers = sorted_numbers("median", args);
+    let middle = numbers.len() / 2;
+    if numbers.len() % 2 == 1 {
+        numbers[middle].clone()
+    } else {
+        mean(numbers[middle - 1..=middle].to_vec())
+    }
+}
+
+pub(crate) fn percentile_impl(args: Vec<JsonPP>) -> JsonPP {
+    assert_eq!(args.len(), 2);
+    let percent = to_f64(&num_arg("percentile", &args, 1));
+    if !(0.0..=100.0).contains(&percent) {
+        panic!(
+            "'percentile' expects a percentage between 0 and 100, found {}",
+            percent
+        );
+    }
+    let numbers = sorted_numbers("percentile", vec![args[0].clone()]);
+
+    // Linear interpolation between the closest ranks
+    let rank = percent / 100.0 * (numbers.len() - 1) as f64;
+    let (lower, fraction) = (rank.floor() as usize, rank.fract());
+    if fraction == 0.0 {
+        return numbers[lower].clone();
+    }
+    let difference = num_pair_op(&SUB, numbers[lower + 1].clone(), numbers[lower].clone());
+    let offset = num_pair_op(&MUL, difference, JsonPP::Float(fraction));
+    num_pair_op(&SUM, numbers[lower].clone(), offset)
+}
+
+fn population_variance(fun: &str, args: Vec<JsonPP>) -> f64 {
+    let numbers: Vec<f64> = stat_numbers(fun, args).iter().map(to_f64).collect();
+    let mean = numbers.iter().sum::<f64>() / numbers.len() as f64;
+    numbers.iter().map(|val| (val - mean).powi(2)).sum::<f64>() / numbers.len() as f64
+}
+
+pub(crate) fn variance_impl(args: Vec<JsonPP>) -> JsonPP {
+    JsonPP::Float(population_variance("variance", args))
+}
+
+pub(crate) fn stddev_impl(args: Vec<JsonPP>) -> JsonPP {
+    JsonPP::Float(population_variance("stddev", args).sqrt())
+}
+
+pub(crate) fn not_impl(args: Vec<JsonPP>) -> JsonPP {
+    assert_eq!(args.len(), 1);
+
+    JsonPP::Bool(!args[0].is_truthy())
+}
+
+pub(crate) fn eq_impl(args: Vec<JsonPP>) -> JsonPP {
+    assert_eq!(args.len(), 2);
+
+    let first_arg = args[0].clone();
+    let second_arg = args[1].clone();
+
+    JsonPP::Bool(first_arg == second_arg)
+}
+
+pub(crate) fn if_impl(args: Vec<JsonPP>) -> JsonPP {
+    assert_eq!(args.len(), 3); // Condition, if true, if not;
+
+    let index = if args[0].is_truthy() { 1 } else { 2 };
+    args[index].clone()
+}
+
+pub(crate) fn include_impl(args: Vec<JsonPP>) -> JsonPP {
+    assert_eq!(args.len(), 1);
+
+    let JsonPP::String(path) = args[0].clone() else {
+        panic!("Include path is not a string")
+    };
+
+    let mut file = File::open(path).unwrap();
+    let mut buffer = vec![];
+    file.read_to_end(&mut buffer).unwrap();
+
+    let string: String = str::from_utf8(&buffer)
+        .expect("File to contain utf-8")
+        .to_owned();
+    JsonPP::String(string.trim().to_owned())
+}
+
+pub(crate) fn import_impl(args: Vec<JsonPP>) -> JsonPP {
+    assert_eq!(args.len(), 1);
+
+    let JsonPP::String(path) = args[0].clone() else {
+        panic!("Import path is not a string")
+    };
+
+    let mut file = File::open(path).unwrap();
+    let mut buffer = vec![];
+    file.read_to_end(&mut buffer).unwrap();
+
+    parse_bytes(buffer)
+}
+
+pub(crate) fn str_impl(args: Vec<JsonPP>) -> JsonPP {
+    assert_eq!(args.len(), 1);
+
+    JsonPP::String(match args[0].clone() {
+        JsonPP::String(val) => val,
+
+        JsonPP::Null => "null".to_owned(),
+        JsonPP::Bool(val) => val.to_string(),
+        JsonPP::Int(val) => val.to_string(),
+        JsonPP::Float(val) => val.to_string(),
+        JsonPP::BigInt(val) => val.to_string(),
+        JsonPP::Decimal(val) => val.to_plain_string(),
+
+        JsonPP::Array(vec) => {
+            format!(
+                "[{}]",
+                vec.into_iter()
+                    .map(|elem| {
+                        let JsonPP::String(val) = str_impl(vec![elem]) else {
+                            panic!("Array element didn't convert to string")
+                        };
+                        val
+                    })
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            )
+        }
+        JsonPP::Object(hash_map) => format!(
+            "{{{}}}",
+            hash_map
+                .into_iter()
+                .map(|(key, elem)| {
+                    let JsonPP::String(val) = str_impl(vec![elem]) else {
+                        panic!("Array element didn't convert to string")
+                    };
+                    format!("\"{}\": {}", key, val)
+                })
+                .collect::<Vec<String>>()
+                .join(", ")
+        ),
+        other => panic!("Can't convert {:?} to string", other),
+    })
+}
+
+pub(crate) fn int_impl(args: Vec<JsonPP>) -> JsonPP {
+    assert_eq!(args.len(), 1);
+
+    JsonPP::Int(match args[0].clone() {
+        JsonPP::Int(val) => val,
+        JsonPP::BigInt(val) => return JsonPP::BigInt(val),
+
+        JsonPP::Null => 0,
+        JsonPP::Bool(val) => val as i64,
+        JsonPP::Float(val) => {
+            let Some(big) = BigInt::from_f64(val.round()) else {
+                panic!("Can't convert {} to int", val)
+            };
+            return JsonPP::from(big);
+        }
+        JsonPP::Decimal(val) => return JsonPP::from(decimal_to_bigint(val)),
+        JsonPP::String(val) => {
+            let Ok(decimal) = val.parse() else {
+                panic!("str to int parse failed: '{}'", val)
+            };
+            return JsonPP::from(decimal_to_bigint(decimal));
+        }
+        other => panic!("Can't convert \"{:?}\" to int", other),
+    })
+}
+
+fn decimal_to_bigint(val: BigDecimal) -> BigInt {
+    // Rounds halves away from zero like f64::round
+    let (big, _) = val
+        .with_scale_round(0, RoundingMode::HalfUp)
+        .into_bigint_and_exponent();
+    big
+}
+
+pub(crate) fn float_impl(args: Vec<JsonPP>) -> JsonPP {
+    assert_eq!(args.len(), 1);
+
+    JsonPP::Float(match args[0].clone() {
+        JsonPP::Float(val) => val,
+
+        JsonPP::Null => 0.0,
+        JsonPP::Bool(val) => val as i64 as f64,
+        JsonPP::Int(val) => val as f64,
+        number @ (JsonPP::BigInt(_) | JsonPP::Decimal(_)) => to_f64(&number),
+        JsonPP::String(val) => val.parse().expect("str to float parse failed"),
+        other => panic!("Can't convert \"{:?}\" to float", other),
+    })
+}
+
+pub(crate) fn decimal_impl(args: Vec<JsonPP>) -> JsonPP {
+    assert_eq!(args.len(), 1);
+
+    JsonPP::Decimal(match args[0].clone() {
+        JsonPP::Null => BigDecimal::zero(),
+        JsonPP::Bool(val) => BigDecimal::from(val as i64),
+        JsonPP::String(val) => val
+            .parse()
+            .unwrap_or_else(|_| panic!("str to decimal parse failed: '{}'", val)),
+        number @ (JsonPP::Int(_) | JsonPP::BigInt(_) | JsonPP::Float(_) | JsonPP::Decimal(_)) => {
+            to_decimal(&number)
+        }
+        other => panic!("Can't convert \"{:?}\" to decimal", other),
+    })
+}
+
+pub(crate) fn range_impl(args: Vec<JsonPP>) -> JsonPP {
+    assert_eq!(args.len(), 2);
+
+    let JsonPP::Int(start) = args[0].clone() else {
+        panic!("Range start is not an int")
     };
     let JsonPP::Int(end) = args[1].clone() else {
         panic!("Range end is not an int")
     };
 
-    JsonPP::Array((start..end).map(JsonPP::Int).collect())
+    JsonPP::Array((start..end).map(JsonPP::Int).collect())
+}
+
+pub(crate) fn merge_impl(args: Vec<JsonPP>) -> JsonPP {
+    // Works on strings, arrays and objects
+    // All participants must be of the same type
+
+    if args.iter().all(|el| matches!(el, JsonPP::String(_))) {
+        return string_merge_impl(args);
+    }
+
+    if args.iter().all(|el| matches!(el, JsonPP::Array(_))) {
+        return array_merge_impl(args);
+    }
+
+    if args.iter().all(|el| matches!(el, JsonPP::Object(_))) {
+        return object_merge_impl(args);
+    }
+
+    panic!("Either mismatched array elements or illegal types of elements in merge");
+}
+
+fn string_merge_impl(args: Vec<JsonPP>) -> JsonPP {
+    // All elements are strings
+    JsonPP::String(
+        args.into_iter()
+            .map(|el| {
+                let JsonPP::String(val) = el else {
+                    unreachable!()
+                };
+
+                val
+            })
+            .collect::<Vec<String>>()
+            .join(""),
+    )
+}
+
+fn array_merge_impl(args: Vec<JsonPP>) -> JsonPP {
+    // All elements are arrays
+    JsonPP::Array(
+        args.into_iter()
+            .flat_map(|el| {
+                let JsonPP::Array(val) = el else {
+                    unreachable!()
+                };
+
+                val
+            })
+            .collect(),
+    )
+}
+
+fn object_merge_impl(args: Vec<JsonPP>) -> JsonPP {
+    // All elements are objects
+    JsonPP::Object(
+        args.into_iter()
+            .flat_map(|el| {
+                let JsonPP::Object(val) = el else {
+                    unreachable!()
+                };
+
+                val
+            })
+            .collect(),
+    )
+}
+
+enum ArrayMerge {
+    Replace,
+    Append,
+    ByKey(String),
+}
+
+struct MergeOptions {
+    arrays: ArrayMerge,
+    delete_nulls: bool,
+    error_on_conflict: bool,
+}
+
+impl MergeOptions {
+    fn parse(options: HashMap<String, JsonPP>) -> Self {
+        let option = |name: &str, default: &str, allowed: &[&str]| match options.get(name) {
+            None => default.to_owned(),
+            Some(JsonPP::String(val)) if allowed.contains(&val.as_str()) => val.clone(),
+            Some(other) => panic!(
+                "'deep_merge_with' option '{}' should be one of {:?}, found {:?}",
+                name, allowed, other
+            ),
+        };
+
+        if let Some(unknown) = options
+            .keys()
+            .find(|key| !["arrays", "key", "nulls", "types"].contains(&key.as_str()))
+        {
+            panic!("Unknown 'deep_merge_with' option '{}'", unknown);
+        }
+
+        let arrays = match (
+            option("arrays", "replace", &["replace", "append", "merge"]).as_str(),
+            options.get("key"),
+        ) {
+            ("merge", Some(JsonPP::String(key))) => ArrayMerge::ByKey(key.clone()),
+            ("merge", other) => panic!(
+                "'deep_merge_with' needs a string 'key' to merge arrays by, found {:?}",
+                other
+            ),
+            (_, Some(_)) => {
+                panic!("'deep_merge_with' option 'key' only works with 'arrays': 'merge'")
+            }
+            ("append", None) => ArrayMerge::Append,
+            _ => ArrayMerge::Replace,
+        };
+
+        MergeOptions {
+            arrays,
+            delete_nulls: option("nulls", "keep", &["keep", "delete"]) == "delete",
+            error_on_conflict: option("types", "replace", &["replace", "error"]) == "error",
+        }
+    }
+}
+
+impl Default for MergeOptions {
+    fn default() -> Self {
+        MergeOptions::parse(HashMap::new())
+    }
+}
+
+fn type_name(value: &JsonPP) -> &'static str {
+    match value {
+        JsonPP::Null => "null",
+        JsonPP::Bool(_) => "bool",
+        JsonPP::String(_) => "string",
+        JsonPP::Int(_) | JsonPP::Float(_) | JsonPP::BigInt(_) | JsonPP::Decimal(_) => "number",
+        JsonPP::Array(_) => "array",
+        JsonPP::Object(_) => "object",
+        _ => "dynamic",
+    }
+}
+
+fn deep_merge_objects(
+    mut base: HashMap<String, JsonPP>,
+    overrides: HashMap<String, JsonPP>,
+    options: &MergeOptions,
+    path: &mut Vec<PathChunk>,
+) -> HashMap<String, JsonPP> {
+    for (key, over) in sorted_entries(overrides) {
+        if over == JsonPP::Null && options.delete_nulls {
+            base.remove(&key);
+            continue;
+        }
+
+        path.push(PathChunk::Key(key.clone()));
+        let merged = match base.remove(&key) {
+            Some(existing) => deep_merge_values(existing, over, options, path),
+            None => without_new_nulls(over, options, path),
+        };
+        path.pop();
+        base.insert(key, merged);
+    }
+    base
+}
+
+fn deep_merge_arrays(
+    base: Vec<JsonPP>,
+    overrides: Vec<JsonPP>,
+    options: &MergeOptions,
+    path: &mut Vec<PathChunk>,
+) -> Vec<JsonPP> {
+    let key = match &options.arrays {
+        ArrayMerge::Replace => return new_elements(overrides, 0, options, path),
+        ArrayMerge::Append => {
+            let start = base.len();
+            return base
+                .into_iter()
+                .chain(new_elements(overrides, start, options, path))
+                .collect();
+        }
+        ArrayMerge::ByKey(key) => key,
+    };
+
+    // Objects with the same value in the key field are merged, everything else is appended
+    let mut merged = base;
+    for over in overrides {
+        let key_value = match &over {
+            JsonPP::Object(obj) => obj.get(key).cloned(),
+            _ => None,
+        };
+        let existing = key_value.and_then(|key_value| {
+            merged.iter().position(|el| match el {
+                JsonPP::Object(obj) => obj.get(key) == Some(&key_value),
+                _ => false,
+            })
+        });
+
+        match existing {
+            Some(index) => {
+                path.push(PathChunk::Index(index));
+                let current = std::mem::replace(&mut merged[index], JsonPP::Null);
+                merged[index] = deep_merge_values(current, over, options, path);
+                path.pop();
+            }
+            None => {
+                path.push(PathChunk::Index(merged.len()));
+                merged.push(without_new_nulls(over, options, path));
+                path.pop();
+            }
+        }
+    }
+    merged
+}
+
+// Values that weren't in the base can have nulls in them too
+fn without_new_nulls(value: JsonPP, options: &MergeOptions, path: &mut Vec<PathChunk>) -> JsonPP {
+    match value {
+        JsonPP::Object(obj) if options.delete_nulls => {
+            JsonPP::Object(deep_merge_objects(HashMap::new(), obj, options, path))
+        }
+        JsonPP::Array(inner) if options.delete_nulls => {
+            JsonPP::Array(new_elements(inner, 0, options, path))
+        }
+        other => other,
+    }
+}
+
+fn new_elements(
+    elements: Vec<JsonPP>,
+    start: usize,
+    options: &MergeOptions,
+    path: &mut Vec<PathChunk>,
+) -> Vec<JsonPP> {
+    elements
+        .into_iter()
+        .enumerate()
+        .map(|(index, el)| {
+            path.push(PathChunk::Index(start + index));
+            let el = without_new_nulls(el, options, path);
+            path.pop();
+            el
+        })
+        .collect()
+}
+
+fn deep_merge_values(
+    base: JsonPP,
+    over: JsonPP,
+    options: &MergeOptions,
+    path: &mut Vec<PathChunk>,
+) -> JsonPP {
+    match (base, over) {
+        (JsonPP::Object(base), JsonPP::Object(over)) => {
+            JsonPP::Object(deep_merge_objects(base, over, options, path))
+        }
+        (JsonPP::Array(base), JsonPP::Array(over)) => {
+            JsonPP::Array(deep_merge_arrays(base, over, options, path))
+        }
+        (base, over) => {
+            let conflicting = !matches!(base, JsonPP::Null)
+                && !matches!(over, JsonPP::Null)
+                && type_name(&base) != type_name(&over);
+            if options.error_on_conflict && conflicting {
+                panic!(
+                    "Can't merge a {} with a {} at '{}'",
+                    type_name(&base),
+                    type_name(&over),
+                    format_path(path)
+                );
+            }
+            over
+        }
+    }
+}
+
+fn deep_merge(fun: &str, objects: &[JsonPP], options: MergeOptions) -> JsonPP {
+    assert!(!objects.is_empty(), "'{}' needs at least one object", fun);
+
+    let mut merged = object_arg(fun, objects, 0);
+    for index in 1..objects.len() {
+        let next = object_arg(fun, objects, index);
+        merged = deep_merge_objects(merged, next, &options, &mut vec![]);
+    }
+    JsonPP::Object(merged)
+}
+
+pub(crate) fn deep_merge_impl(args: Vec<JsonPP>) -> JsonPP {
+    deep_merge("deep_merge", &args, MergeOptions::default())
+}
+
+pub(crate) fn deep_merge_with_impl(args: Vec<JsonPP>) -> JsonPP {
+    assert!(!args.is_empty());
+    let options = MergeOptions::parse(object_arg("deep_merge_with", &args, 0));
+    deep_merge("deep_merge_with", &args[1..], options)
+}
+
+fn string_arg(fun: &str, args: &[JsonPP], index: usize) -> String {
+    let JsonPP::String(val) = &args[index] else {
+        panic!(
+            "'{}' expects a string as argument {}, found {:?}",
+            fun, index, args[index]
+        );
+    };
+
+    val.to_owned()
+}
+
+fn int_arg(fun: &str, args: &[JsonPP], index: usize) -> i64 {
+    let JsonPP::Int(val) = &args[index] else {
+        panic!(
+            "'{}' expects an int as argument {}, found {:?}",
+            fun, index, args[index]
+        );
+    };
+
+    *val
+}
+
+fn array_arg(fun: &str, args: &[JsonPP], index: usize) -> Vec<JsonPP> {
+    let JsonPP::Array(val) = &args[index] else {
+        panic!(
+            "'{}' expects an array as argument {}, found {:?}",
+            fun, index, args[index]
+        );
+    };
+
+    val.to_owned()
+}
+
+fn optional_int_arg(fun: &str, args: &[JsonPP], index: usize) -> Option<i64> {
+    match args.get(index) {
+        None | Some(JsonPP::Null) => None,
+        Some(_) => Some(int_arg(fun, args, index)),
+    }
+}
+
+fn object_arg(fun: &str, args: &[JsonPP], index: usize) -> HashMap<String, JsonPP> {
+    let JsonPP::Object(val) = &args[index] else {
+        panic!(
+            "'{}' expects an object as argument {}, found {:?}",
+            fun, index, args[index]
+        );
+    };
+
+    val.to_owned()
+}
+
+fn string_array(strings: impl Iterator<Item = impl Into<String>>) -> JsonPP {
+    JsonPP::Array(strings.map(|val| JsonPP::String(val.into())).collect())
+}
+
+pub(crate) fn upper_impl(args: Vec<JsonPP>) -> JsonPP {
+    assert_eq!(args.len(), 1);
+    JsonPP::String(string_arg("upper", &args, 0).to_uppercase())
+}
+
+pub(crate) fn lower_impl(args: Vec<JsonPP>) -> JsonPP {
+    assert_eq!(args.len(), 1);
+    JsonPP::String(string_arg("lower", &args, 0).to_lowercase())
+}
+
+pub(crate) fn trim_impl(args: Vec<JsonPP>) -> JsonPP {
+    assert_eq!(args.len(), 1);
+    JsonPP::String(string_arg("trim", &args, 0).trim().to_owned())
+}
+
+pub(crate) fn trim_start_impl(args: Vec<JsonPP>) -> JsonPP {
+    assert_eq!(args.len(), 1);
+    JsonPP::String(string_arg("trim_start", &args, 0).trim_start().to_owned())
+}
+
+pub(crate) fn trim_end_impl(args: Vec<JsonPP>) -> JsonPP {
+    assert_eq!(args.len(), 1);
+    JsonPP::String(string_arg("trim_end", &args, 0).trim_end().to_owned())
+}
+
+pub(crate) fn split_impl(args: Vec<JsonPP>) -> JsonPP {
+    assert_eq!(args.len(), 2);
+    let string = string_arg("split", &args, 0);
+    let separator = string_arg("split", &args, 1);
+
+    if separator.is_empty() {
+        // Splitting on nothing gives the characters, without empty strings at the ends
+        return string_array(string.chars().map(String::from));
+    }
+
+    string_array(string.split(&separator))
+}
+
+pub(crate) fn join_impl(args: Vec<JsonPP>) -> JsonPP {
+    assert!(args.len() == 1 || args.len() == 2);
+    let JsonPP::Array(arr) = &args[0] else {
+        panic!("'join' expects an array as argument 0, found {:?}", args[0]);
+    };
+    let separator = if args.len() == 2 {
+        string_arg("join", &args, 1)
+    } else {
+        "".to_owned()
+    };
+
+    JsonPP::String(
+        arr.iter()
+            .enumerate()
+            .map(|(index, el)| {
+                let JsonPP::String(val) = el else {
+                    panic!(
+                        "'join' expects a string as element {} of the array in argument 0, found {:?}",
+                        index, el
+                    );
+                };
+                val.to_owned()
+            })
+            .collect::<Vec<String>>()
+            .join(&separator),
+    )
+}
+
+pub(crate) fn replace_impl(args: Vec<JsonPP>) -> JsonPP {
+    assert_eq!(args.len(), 3);
+    let string = string_arg("replace", &args, 0);
+    let from = string_arg("replace", &args, 1);
+    let to = string_arg("replace", &args, 2);
+
+    JsonPP::String(string.replace(&from, &to))
+}
+
+pub(crate) fn contains_impl(args: Vec<JsonPP>) -> JsonPP {
+    assert_eq!(args.len(), 2);
+    if let JsonPP::Array(arr) = &args[0] {
+        return JsonPP::Bool(arr.contains(&args[1]));
+    }
+    JsonPP::Bool(string_arg("contains", &args, 0).contains(&string_arg("contains", &args, 1)))
+}
+
+pub(crate) fn starts_with_impl(args: Vec<JsonPP>) -> JsonPP {
+    assert_eq!(args.len(), 2);
+    JsonPP::Bool(string_arg("starts_with", &args, 0).starts_with(&string_arg(
+        "starts_with",
+        &args,
+        1,
+    )))
+}
+
+pub(crate) fn ends_with_impl(args: Vec<JsonPP>) -> JsonPP {
+    assert_eq!(args.len(), 2);
+    JsonPP::Bool(string_arg("ends_with", &args, 0).ends_with(&string_arg("ends_with", &args, 1)))
+}
+
+pub(crate) fn repeat_impl(args: Vec<JsonPP>) -> JsonPP {
+    assert_eq!(args.len(), 2);
+    let string = string_arg("repeat", &args, 0);
+    let times = int_arg("repeat", &args, 1);
+
+    if times < 0 {
+        panic!("Can't repeat a string {} times", times);
+    }
+
+    JsonPP::String(string.repeat(times as usize))
+}
+
+pub(crate) fn pad_start_impl(args: Vec<JsonPP>) -> JsonPP {
+    let (string, padding) = padding("pad_start", args);
+    JsonPP::String(padding + &string)
+}
+
+pub(crate) fn pad_end_impl(args: Vec<JsonPP>) -> JsonPP {
+    let (string, padding) = padding("pad_end", args);
+    JsonPP::String(string + &padding)
+}
+
+fn padding(fun: &str, args: Vec<JsonPP>) -> (String, String) {
+    // Returns the string and the padding needed to make it the requested length in characters
+    assert!(args.len() == 2 || args.len() == 3);
+    let string = string_arg(fun, &args, 0);
+    let target_len = int_arg(fun, &args, 1);
+    let fill = if args.len() == 3 {
+        string_arg(fun, &args, 2)
+    } else {
+        " ".to_owned()
+    };
+
+    if fill.is_empty() {
+        panic!("'{}' can't pad with an empty string", fun);
+    }
+
+    let missing = (target_len.max(0) as usize).saturating_sub(string.chars().count());
+    let padding = fill.chars().cycle().take(missing).collect();
+    (string, padding)
+}
+
+pub(crate) fn byte_len_impl(args: Vec<JsonPP>) -> JsonPP {
+    assert_eq!(args.len(), 1);
+    JsonPP::Int(string_arg("byte_len", &args, 0).len() as i64)
+}
+
+pub(crate) fn grapheme_len_impl(args: Vec<JsonPP>) -> JsonPP {
+    assert_eq!(args.len(), 1);
+    JsonPP::Int(string_arg("grapheme_len", &args, 0).graphemes(true).count() as i64)
+}
+
+fn char_index(index: i64, len: usize) -> usize {
+    // Negative indices count from the end, out of bounds gets clamped
+    if index < 0 {
+        len.saturating_sub(index.unsigned_abs() as usize)
+    } else {
+        (index as usize).min(len)
+    }
+}
+
+pub(crate) fn substr_impl(args: Vec<JsonPP>) -> JsonPP {
+    assert!(args.len() == 2 || args.len() == 3);
+    let string = string_arg("substr", &args, 0);
+    let char_count = string.chars().count();
+    let start = char_index(int_arg("substr", &args, 1), char_count);
+    let len = if args.len() == 3 {
+        int_arg("substr", &args, 2).max(0) as usize
+    } else {
+        char_count
+    };
+
+    JsonPP::String(string.chars().skip(start).take(len).collect())
+}
+
+pub(crate) fn char_at_impl(args: Vec<JsonPP>) -> JsonPP {
+    assert_eq!(args.len(), 2);
+    let string = string_arg("char_at", &args, 0);
+    let index = int_arg("char_at", &args, 1);
+    let char_count = string.chars().count();
+
+    let absolute = if index < 0 {
+        char_count as i64 + index
+    } else {
+        index
+    };
+
+    let Some(found) = usize::try_from(absolute)
+        .ok()
+        .and_then(|absolute| string.chars().nth(absolute))
+    else {
+        panic!(
+            "Index {} is out of bounds for '{}', which has {} characters",
+            index, string, char_count
+        );
+    };
+
+    JsonPP::String(found.to_string())
+}
+
+pub(crate) fn chars_impl(args: Vec<JsonPP>) -> JsonPP {
+    assert_eq!(args.len(), 1);
+    string_array(string_arg("chars", &args, 0).chars().map(String::from))
+}
+
+pub(crate) fn lines_impl(args: Vec<JsonPP>) -> JsonPP {
+    assert_eq!(args.len(), 1);
+    string_array(string_arg("lines", &args, 0).lines())
+}
+
+pub(crate) fn format_impl(args: Vec<JsonPP>) -> JsonPP {
+    assert!(!args.is_empty());
+    let template = string_arg("format", &args, 0);
+    let values = &args[1..];
+
+    let mut out = String::new();
+    let mut stream = template.chars().peekable();
+    let mut next_positional = 0;
+
+    while let Some(next_char) = stream.next() {
+        match next_char {
+            '{' if stream.peek() == Some(&'{') => {
+                stream.next();
+                out.push('{');
+            }
+            '}' if stream.peek() == Some(&'}') => {
+                stream.next();
+                out.push('}');
+            }
+            '{' => {
+                let mut placeholder = String::new();
+                loop {
+                    match stream.next() {
+                        Some('}') => break,
+                        Some(ch) => placeholder.push(ch),
+                        None => panic!("Unmatched '{{' in format string '{}'", template),
+                    }
+                }
+                let (name, spec) = placeholder.split_once(':').unwrap_or((&placeholder, ""));
+
+                let value = if name.is_empty() {
+                    next_positional += 1;
+                    values.get(next_positional - 1)
+                } else if let Ok(index) = name.parse::<usize>() {
+                    values.get(index)
+                } else {
+                    // Names are paths into the first value
+                    values.first().and_then(|first| {
+                        evaluation::abs_fetch(
+                            &make_absolute(&[], &ref_chain(name.to_owned())),
+                            first,
+                        )
+                    })
+                };
+
+                let Some(value) = value else {
+                    panic!(
+                        "Nothing to format for '{{{}}}' in '{}'",
+                        placeholder, template
+                    );
+                };
+                out.push_str(&format_value(value, spec));
+            }
+            '}' => panic!("Unmatched '}}' in format string '{}'", template),
+            other => out.push(other),
+        }
+    }
+
+    JsonPP::String(out)
+}
+
+fn format_value(value: &JsonPP, spec: &str) -> String {
+    // Roughly follows the rust format spec: [[fill]align][+][0][width][.precision]
+    let mut chars: Vec<char> = spec.chars().collect();
+    let aligns = ['<', '>', '^'];
+
+    let (fill, align) = match chars.as_slice() {
+        [fill, align, ..] if aligns.contains(align) => {
+            let out = (*fill, Some(*align));
+            chars.drain(..2);
+            out
+        }
+        [align, ..] if aligns.contains(align) => {
+            let out = (' ', Some(*align));
+            chars.drain(..1);
+            out
+        }
+        _ => (' ', None),
+    };
+
+    let plus_sign = chars.first() == Some(&'+');
+    if plus_sign {
+        chars.remove(0);
+    }
+
+    let zero_pad = chars.first() == Some(&'0');
+    if zero_pad {
+        chars.remove(0);
+    }
+
+    let rest: String = chars.into_iter().collect();
+    let (width, precision) = rest.split_once('.').unwrap_or((&rest, ""));
+    let parse = |part: &str| {
+        (!part.is_empty()).then(|| {
+            part.parse::<usize>()
+                .unwrap_or_else(|_| panic!("Invalid format spec '{}'", spec))
+        })
+    };
+    let width = parse(width).unwrap_or(0);
+    let precision = parse(precision);
+
+    let (numeric, mut text) = match (value, precision) {
+        (JsonPP::Int(val), None) => (true, val.to_string()),
+        (JsonPP::Int(val), Some(precision)) => (true, format!("{:.*}", precision, *val as f64)),
+        (JsonPP::Float(val), None) => (true, val.to_string()),
+        (JsonPP::Float(val), Some(precision)) => (true, format!("{:.*}", precision, val)),
+        (JsonPP::BigInt(val), None) => (true, val.to_string()),
+        (number @ (JsonPP::BigInt(_) | JsonPP::Decimal(_)), Some(precision)) => {
+            let rounded =
+                to_decimal(number).with_scale_round(precision as i64, RoundingMode::HalfUp);
+            (true, rounded.to_plain_string())
+        }
+        (JsonPP::Decimal(val), None) => (true, val.to_plain_string()),
+        (other, precision) => {
+            let JsonPP::String(text) = str_impl(vec![other.clone()]) else {
+                unreachable!()
+            };
+            // Precision on strings is the maximum length
+            let text = match precision {
+                Some(precision) => text.chars().take(precision).collect(),
+                None => text,
+            };
+            (false, text)
+        }
+    };
+
+    if numeric && plus_sign && !text.starts_with('-') {
+        text.insert(0, '+');
+    }
+
+    let missing = width.saturating_sub(text.chars().count());
+    if missing == 0 {
+        return text;
+    }
+
+    if numeric && zero_pad && align.is_none() {
+        // Zeroes go between the sign and the digits
+        let sign_len = if text.starts_with(['-', '+']) { 1 } else { 0 };
+        text.insert_str(sign_len, &"0".repeat(missing));
+        return text;
+    }
+
+    let padding = |amount: usize| fill.to_string().repeat(amount);
+    match align.unwrap_or(if numeric { '>' } else { '<' }) {
+        '<' => text + &padding(missing),
+        '>' => padding(missing) + &text,
+        _ => padding(missing / 2) + &text + &padding(missing - missing / 2),
+    }
+}
+
+const REGEX_CACHE_SIZE: usize = 32;
+
+thread_local! {
+    // Patterns are often used in maps, no need to compile them for every element
+    // Most recently used first, only the last few are kept
+    static REGEX_CACHE: RefCell<VecDeque<(String, Regex)>> = const { RefCell::new(VecDeque::new()) };
+}
+
+fn regex_arg(fun: &str, args: &[JsonPP], index: usize) -> Regex {
+    let pattern = string_arg(fun, args, index);
+
+    REGEX_CACHE.with_borrow_mut(|cache| {
+        let entry = match cache.iter().position(|(cached, _)| *cached == pattern) {
+            Some(position) => cache.remove(position).unwrap(),
+            None => {
+                let regex = Regex::new(&pattern).unwrap_or_else(|err| {
+                    panic!("Invalid regex '{}' in '{}': {}", pattern, fun, err)
+                });
+                cache.truncate(REGEX_CACHE_SIZE - 1);
+                (pattern, regex)
+            }
+        };
+
+        let regex = entry.1.clone();
+        cache.push_front(entry);
+        regex
+    })
+}
+
+pub(crate) fn re_match_impl(args: Vec<JsonPP>) -> JsonPP {
+    assert_eq!(args.len(), 2);
+    let regex = regex_arg("re_match", &args, 0);
+
+    JsonPP::Bool(regex.is_match(&string_arg("re_match", &args, 1)))
+}
+
+pub(crate) fn re_find_impl(args: Vec<JsonPP>) -> JsonPP {
+    assert_eq!(args.len(), 2);
+    let regex = regex_arg("re_find", &args, 0);
+    let string = string_arg("re_find", &args, 1);
+
+    let Some(captures) = regex.captures(&string) else {
+        return JsonPP::Null;
+    };
+    let group = |matched: Option<regex::Match>| {
+        matched.map_or(JsonPP::Null, |inner| {
+            JsonPP::String(inner.as_str().to_owned())
+        })
+    };
+
+    if regex.capture_names().any(|name| name.is_some()) {
+        // Named groups are more useful by name
+        JsonPP::Object(
+            regex
+                .capture_names()
+                .flatten()
+                .map(|name| (name.to_owned(), group(captures.name(name))))
+                .collect(),
+        )
+    } else {
+        // First one is the whole match, then groups in order
+        JsonPP::Array(captures.iter().map(group).collect())
+    }
+}
+
+pub(crate) fn re_replace_impl(args: Vec<JsonPP>) -> JsonPP {
+    assert_eq!(args.len(), 3);
+    let regex = regex_arg("re_replace", &args, 0);
+    let replacement = string_arg("re_replace", &args, 1);
+    let string = string_arg("re_replace", &args, 2);
+
+    JsonPP::String(
+        regex
+            .replace_all(&string, replacement.as_str())
+            .into_owned(),
+    )
+}
+
+pub(crate) fn re_split_impl(args: Vec<JsonPP>) -> JsonPP {
+    assert_eq!(args.len(), 2);
+    let regex = regex_arg("re_split", &args, 0);
+
+    string_array(regex.split(&string_arg("re_split", &args, 1)))
+}
+
+fn decoded_string(fun: &str, bytes: Vec<u8>) -> JsonPP {
+    JsonPP::String(
+        String::from_utf8(bytes)
+            .unwrap_or_else(|_| panic!("'{}' decoded into something that isn't utf-8", fun)),
+    )
+}
+
+pub(crate) fn base64_encode_impl(args: Vec<JsonPP>) -> JsonPP {
+    assert_eq!(args.len(), 1);
+    JsonPP::String(BASE64_STANDARD.encode(string_arg("base64_encode", &args, 0)))
+}
+
+pub(crate) fn base64_decode_impl(args: Vec<JsonPP>) -> JsonPP {
+    assert_eq!(args.len(), 1);
+    let encoded = string_arg("base64_decode", &args, 0);
+    let bytes = BASE64_STANDARD
+        .decode(&encoded)
+        .unwrap_or_else(|err| panic!("Invalid base64 '{}': {}", encoded, err));
+
+    decoded_string("base64_decode", bytes)
+}
+
+pub(crate) fn hex_encode_impl(args: Vec<JsonPP>) -> JsonPP {
+    assert_eq!(args.len(), 1);
+    JsonPP::String(hex::encode(string_arg("hex_encode", &args, 0)))
+}
+
+pub(crate) fn hex_decode_impl(args: Vec<JsonPP>) -> JsonPP {
+    assert_eq!(args.len(), 1);
+    let encoded = string_arg("hex_decode", &args, 0);
+    let bytes =
+        hex::decode(&encoded).unwrap_or_else(|err| panic!("Invalid hex '{}': {}", encoded, err));
+
+    decoded_string("hex_decode", bytes)
+}
+
+// Everything except the unreserved characters of RFC 3986
+const URL_ENCODED: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+pub(crate) fn url_encode_impl(args: Vec<JsonPP>) -> JsonPP {
+    assert_eq!(args.len(), 1);
+    JsonPP::String(
+        utf8_percent_encode(&string_arg("url_encode", &args, 0), URL_ENCODED).to_string(),
+    )
+}
+
+pub(crate) fn url_decode_impl(args: Vec<JsonPP>) -> JsonPP {
+    assert_eq!(args.len(), 1);
+    let bytes: Vec<u8> = percent_decode_str(&string_arg("url_decode", &args, 0)).collect();
+
+    decoded_string("url_decode", bytes)
+}
+
+pub(crate) fn to_json_impl(args: Vec<JsonPP>) -> JsonPP {
+    assert!(args.len() == 1 || args.len() == 2);
+    let pretty = args.get(1).is_some_and(JsonPP::is_truthy);
+
+    let Ok(json) = args[0].to_json_string(pretty) else {
+        panic!("Can't convert {:?} to json", args[0]);
+    };
+
+    JsonPP::String(json)
+}
+
+pub(crate) fn from_json_impl(args: Vec<JsonPP>) -> JsonPP {
+    assert_eq!(args.len(), 1);
+    let string = string_arg("from_json", &args, 0);
+
+    if let Err(err) = serde_json::from_str::<serde_json::Value>(&string) {
+        panic!("Invalid json '{}': {}", string, err);
+    }
+
+    // Parsed again like the input files, serde_json would round big numbers to floats
+    ast_builder::build_ast(tokenizing::tokenize_json(&string))
+}
+
+fn hash_input(value: &JsonPP) -> Vec<u8> {
+    // Strings are hashed as is, everything else as json with sorted keys, which is stable
+    match value {
+        JsonPP::String(val) => val.as_bytes().to_vec(),
+        other => {
+            let Ok(json) = other.to_json_string(false) else {
+                panic!("Can't hash {:?}", other);
+            };
+            json.into_bytes()
+        }
+    }
+}
+
+fn digest_impl<D: Digest>(args: Vec<JsonPP>) -> JsonPP {
+    assert_eq!(args.len(), 1);
+    JsonPP::String(hex::encode(D::digest(hash_input(&args[0]))))
+}
+
+pub(crate) fn sha256_impl(args: Vec<JsonPP>) -> JsonPP {
+    digest_impl::<Sha256>(args)
+}
+
+pub(crate) fn sha1_impl(args: Vec<JsonPP>) -> JsonPP {
+    digest_impl::<Sha1>(args)
+}
+
+pub(crate) fn md5_impl(args: Vec<JsonPP>) -> JsonPP {
+    digest_impl::<Md5>(args)
+}
+
+pub(crate) fn crc32_impl(args: Vec<JsonPP>) -> JsonPP {
+    assert_eq!(args.len(), 1);
+    JsonPP::String(format!("{:08x}", crc32fast::hash(&hash_input(&args[0]))))
+}
+
+fn format_uuid(mut bytes: [u8; 16], version: u8) -> JsonPP {
+    bytes[6] = (bytes[6] & 0x0f) | (version << 4);
+    bytes[8] = (bytes[8] & 0x3f) | 0x80; // RFC 4122 variant
+
+    let hex = hex::encode(bytes);
+    JsonPP::String(format!(
+        "{}-{}-{}-{}-{}",
+        &hex[0..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..32]
+    ))
+}
+
+fn uuid_namespace(namespace: &str) -> [u8; 16] {
+    // The predefined namespaces from RFC 4122 can be used by name
+    let uuid = match namespace {
+        "dns" => "6ba7b810-9dad-11d1-80b4-00c04fd430c8",
+        "url" => "6ba7b811-9dad-11d1-80b4-00c04fd430c8",
+        "oid" => "6ba7b812-9dad-11d1-80b4-00c04fd430c8",
+        "x500" => "6ba7b814-9dad-11d1-80b4-00c04fd430c8",
+        other => other,
+    };
+
+    hex::decode(uuid.replace('-', ""))
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .unwrap_or_else(|| panic!("Invalid uuid namespace '{}'", namespace))
+}
+
+pub(crate) fn uuid_v5_impl(args: Vec<JsonPP>) -> JsonPP {
+    assert_eq!(args.len(), 2);
+    let namespace = uuid_namespace(&string_arg("uuid_v5", &args, 0));
+
+    let mut hasher = Sha1::new();
+    hasher.update(namespace);
+    hasher.update(hash_input(&args[1]));
+    let digest = hasher.finalize();
+
+    format_uuid(digest[..16].try_into().unwrap(), 5)
+}
+
+pub(crate) fn uuid_v4_impl(args: Vec<JsonPP>) -> JsonPP {
+    // Random looking, but the same seed always gives the same uuid
+    assert_eq!(args.len(), 1);
+    let digest = Sha256::digest(hash_input(&args[0]));
+
+    format_uuid(digest[..16].try_into().unwrap(), 4)
+}
+
+pub(crate) fn slug_impl(args: Vec<JsonPP>) -> JsonPP {
+    assert!(args.len() == 1 || args.len() == 2);
+    let string = string_arg("slug", &args, 0);
+
+    // Decompose to drop accents, then lowercase ascii alphanumerics separated by single dashes
+    let cleaned: String = string
+        .nfd()
+        .filter(|ch| !is_combining_mark(*ch))
+        .flat_map(char::to_lowercase)
+        .map(|ch| if ch.is_ascii_alphanumeric() { ch } else { '-' })
+        .collect();
+    let mut slug = cleaned
+        .split('-')
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<&str>>()
+        .join("-");
+    if slug.is_empty() {
+        panic!("'slug' found no letters or digits in '{}'", string);
+    }
+
+    if args.len() == 2 {
+        // DNS labels can be at most 63 characters
+        let max_len = int_arg("slug", &args, 1).max(0) as usize;
+        slug.truncate(max_len);
+        slug = slug.trim_end_matches('-').to_owned();
+    }
+
+    JsonPP::String(slug)
 }
 
-pub(crate) fn merge_impl(args: Vec<JsonPP>) -> JsonPP {
-    // Works on strings, arrays and objects
-    // All participants must be of the same type
+// SplitMix64, seeded from the hash of the seed value. Every call starts from its
+// own seed, so the results don't depend on which order things are evaluated in.
+struct SeededRng(u64);
 
-    if args.iter().all(|el| matches!(el, JsonPP::String(_))) {
-        return string_merge_impl(args);
+impl SeededRng {
+    fn new(seed: &JsonPP) -> Self {
+        let digest = Sha256::digest(hash_input(seed));
+        SeededRng(u64::from_le_bytes(digest[..8].try_into().unwrap()))
     }
 
-    if args.iter().all(|el| matches!(el, JsonPP::Array(_))) {
-        return array_merge_impl(args);
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
     }
 
-    if args.iter().all(|el| matches!(el, JsonPP::Object(_))) {
-        return object_merge_impl(args);
+    fn next_f64(&mut self) -> f64 {
+        // 53 random bits fill the mantissa exactly
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
     }
 
-    panic!("Either mismatched array elements or illegal types of elements in merge");
+    fn below(&mut self, bound: u64) -> u64 {
+        // Rejecting the uneven tail keeps the distribution uniform
+        let zone = u64::MAX - u64::MAX % bound;
+        loop {
+            let value = self.next_u64();
+            if value < zone {
+                return value % bound;
+            }
+        }
+    }
 }
 
-fn string_merge_impl(args: Vec<JsonPP>) -> JsonPP {
-    // All elements are strings
-    JsonPP::String(
-        args.into_iter()
-            .map(|el| {
-                let JsonPP::String(val) = el else {
-                    unreachable!()
-                };
+pub(crate) fn random_impl(args: Vec<JsonPP>) -> JsonPP {
+    assert_eq!(args.len(), 1);
+    JsonPP::Float(SeededRng::new(&args[0]).next_f64())
+}
 
-                val
-            })
-            .collect::<Vec<String>>()
-            .join(""),
-    )
+pub(crate) fn random_int_impl(args: Vec<JsonPP>) -> JsonPP {
+    assert_eq!(args.len(), 3);
+    let low = int_arg("random_int", &args, 1);
+    let high = int_arg("random_int", &args, 2);
+    if low >= high {
+        panic!(
+            "'random_int' needs low to be below high, got {} and {}",
+            low, high
+        );
+    }
+
+    let span = high.abs_diff(low);
+    let offset = SeededRng::new(&args[0]).below(span);
+    JsonPP::Int(low.wrapping_add_unsigned(offset))
 }
 
-fn array_merge_impl(args: Vec<JsonPP>) -> JsonPP {
-    // All elements are arrays
-    JsonPP::Array(
-        args.into_iter()
-            .flat_map(|el| {
-                let JsonPP::Array(val) = el else {
-                    unreachable!()
-                };
+fn shuffled(fun: &str, args: &[JsonPP]) -> Vec<JsonPP> {
+    let JsonPP::Array(mut vec) = args[1].clone() else {
+        panic!(
+            "'{}' expects an array as argument 1, found {:?}",
+            fun, args[1]
+        );
+    };
 
-                val
-            })
-            .collect(),
-    )
+    // Fisher-Yates
+    let mut rng = SeededRng::new(&args[0]);
+    for index in (1..vec.len()).rev() {
+        let other = rng.below(index as u64 + 1) as usize;
+        vec.swap(index, other);
+    }
+    vec
 }
 
-fn object_merge_impl(args: Vec<JsonPP>) -> JsonPP {
-    // All elements are objects
-    JsonPP::Object(
-        args.into_iter()
-            .flat_map(|el| {
-                let JsonPP::Object(val) = el else {
-                    unreachable!()
-                };
+pub(crate) fn shuffle_impl(args: Vec<JsonPP>) -> JsonPP {
+    assert_eq!(args.len(), 2);
+    JsonPP::Array(shuffled("shuffle", &args))
+}
 
-                val
-            })
-            .collect(),
-    )
+pub(crate) fn sample_impl(args: Vec<JsonPP>) -> JsonPP {
+    assert_eq!(args.len(), 3);
+    let count = int_arg("sample", &args, 2);
+    let mut vec = shuffled("sample", &args);
+    if count < 0 || count as usize > vec.len() {
+        panic!(
+            "'sample' can't take {} elements out of {}",
+            count,
+            vec.len()
+        );
+    }
+
+    vec.truncate(count as usize);
+    JsonPP::Array(vec)
 }
 
 pub(crate) fn def_impl(args: Vec<JsonPP>) -> JsonPP {
@@ -474,6 +2280,287 @@ pub(crate) fn reduce_impl(args: Vec<JsonPP>) -> JsonPP {
     }
 }
 
+// Callbacks are called by expanding into the same function, marked as taking the results
+// as an extra argument, which the evaluation fills in before calling it again
+fn expand_calls(fun: &str, args: &[JsonPP], calls: Vec<Vec<JsonPP>>) -> JsonPP {
+    let mut expanded = vec![JsonPP::Identifier(fun.to_owned())];
+    expanded.extend(args.iter().cloned());
+    expanded.push(JsonPP::Array(
+        calls
+            .into_iter()
+            .map(|call_args| {
+                JsonPP::Dynamic(Dynamic {
+                    args: call_args,
+                    ..Default::default()
+                })
+            })
+            .collect(),
+    ));
+
+    JsonPP::Dynamic(Dynamic {
+        args: expanded,
+        results: true,
+        ..Default::default()
+    })
+}
+
+pub(crate) fn call_for_each(fun: &str, args: Vec<JsonPP>) -> JsonPP {
+    assert!(
+        args.len() == 2,
+        "'{}' takes a function and an array, found {} arguments",
+        fun,
+        args.len()
+    );
+    let calls = array_arg(fun, &args, 1)
+        .into_iter()
+        .map(|el| vec![args[0].clone(), el]);
+    expand_calls(fun, &args, calls.collect())
+}
+
+fn with_results(fun: &str, args: &[JsonPP]) -> (Vec<JsonPP>, Vec<JsonPP>) {
+    assert_eq!(args.len(), 3);
+    let arr = array_arg(fun, args, 1);
+    let results = array_arg(fun, args, 2);
+    assert_eq!(arr.len(), results.len());
+    (arr, results)
+}
+
+fn sort_order(fun: &str, first: &JsonPP, second: &JsonPP) -> Ordering {
+    match (first, second) {
+        (JsonPP::String(first), JsonPP::String(second)) => first.cmp(second),
+        _ => num_ordering(first, second).unwrap_or_else(|| {
+            panic!(
+                "'{}' can only order numbers or strings, found {:?} and {:?}",
+                fun, first, second
+            )
+        }),
+    }
+}
+
+pub(crate) fn sort_impl(args: Vec<JsonPP>) -> JsonPP {
+    assert_eq!(args.len(), 1);
+    let mut arr = array_arg("sort", &args, 0);
+    arr.sort_by(|a, b| sort_order("sort", a, b));
+    JsonPP::Array(arr)
+}
+
+pub(crate) fn sort_by_impl(args: Vec<JsonPP>) -> JsonPP {
+    let (arr, keys) = with_results("sort_by", &args);
+
+    let mut pairs: Vec<(JsonPP, JsonPP)> = keys.into_iter().zip(arr).collect();
+    pairs.sort_by(|(a, _), (b, _)| sort_order("sort_by", a, b));
+    JsonPP::Array(pairs.into_iter().map(|(_, el)| el).collect())
+}
+
+pub(crate) fn find_impl(args: Vec<JsonPP>) -> JsonPP {
+    let (arr, conditions) = with_results("find", &args);
+
+    arr.into_iter()
+        .zip(conditions)
+        .find(|(_, cond)| cond.is_truthy())
+        .map_or(JsonPP::Null, |(el, _)| el)
+}
+
+fn group_key(fun: &str, key: JsonPP) -> String {
+    match key {
+        JsonPP::String(key) => key,
+        JsonPP::Array(_) | JsonPP::Object(_) => {
+            panic!("'{}' needs keys to be strings, found {:?}", fun, key)
+        }
+        other => {
+            let JsonPP::String(key) = str_impl(vec![other]) else {
+                unreachable!()
+            };
+            key
+        }
+    }
+}
+
+pub(crate) fn group_by_impl(args: Vec<JsonPP>) -> JsonPP {
+    let (arr, keys) = with_results("group_by", &args);
+
+    let mut groups: HashMap<String, JsonPP> = HashMap::new();
+    for (el, key) in arr.into_iter().zip(keys) {
+        let group = groups
+            .entry(group_key("group_by", key))
+            .or_insert_with(|| JsonPP::Array(vec![]));
+        let JsonPP::Array(members) = group else {
+            unreachable!()
+        };
+        members.push(el);
+    }
+    JsonPP::Object(groups)
+}
+
+pub(crate) fn partition_impl(args: Vec<JsonPP>) -> JsonPP {
+    let (arr, conditions) = with_results("partition", &args);
+
+    let (matching, rest): (Vec<_>, Vec<_>) = arr
+        .into_iter()
+        .zip(conditions)
+        .partition(|(_, cond)| cond.is_truthy());
+    let elements =
+        |pairs: Vec<(JsonPP, JsonPP)>| JsonPP::Array(pairs.into_iter().map(|(el, _)| el).collect());
+    JsonPP::Array(vec![elements(matching), elements(rest)])
+}
+
+pub(crate) fn count_by_impl(args: Vec<JsonPP>) -> JsonPP {
+    let (_, keys) = with_results("count_by", &args);
+
+    let mut counts: HashMap<String, i64> = HashMap::new();
+    for key in keys {
+        *counts.entry(group_key("count_by", key)).or_default() += 1;
+    }
+    JsonPP::Object(
+        counts
+            .into_iter()
+            .map(|(key, count)| (key, JsonPP::Int(count)))
+            .collect(),
+    )
+}
+
+pub(crate) fn index_by_impl(args: Vec<JsonPP>) -> JsonPP {
+    let (arr, keys) = with_results("index_by", &args);
+
+    // Later elements replace earlier ones with the same key
+    JsonPP::Object(
+        keys.into_iter()
+            .map(|key| group_key("index_by", key))
+            .zip(arr)
+            .collect(),
+    )
+}
+
+pub(crate) fn reverse_impl(args: Vec<JsonPP>) -> JsonPP {
+    assert_eq!(args.len(), 1);
+    let mut arr = array_arg("reverse", &args, 0);
+    arr.reverse();
+    JsonPP::Array(arr)
+}
+
+pub(crate) fn unique_impl(args: Vec<JsonPP>) -> JsonPP {
+    assert_eq!(args.len(), 1);
+    let mut unique: Vec<JsonPP> = vec![];
+    for el in array_arg("unique", &args, 0) {
+        if !unique.contains(&el) {
+            unique.push(el);
+        }
+    }
+    JsonPP::Array(unique)
+}
+
+fn flatten_into(target: &mut Vec<JsonPP>, arr: Vec<JsonPP>, depth: i64) {
+    for el in arr {
+        match el {
+            JsonPP::Array(inner) if depth > 0 => flatten_into(target, inner, depth - 1),
+            other => target.push(other),
+        }
+    }
+}
+
+pub(crate) fn flatten_impl(args: Vec<JsonPP>) -> JsonPP {
+    assert!(args.len() == 1 || args.len() == 2);
+    let depth = optional_int_arg("flatten", &args, 1).unwrap_or(1);
+    if depth < 0 {
+        panic!("'flatten' expects a depth of at least 0, found {}", depth);
+    }
+
+    let mut flat = vec![];
+    flatten_into(&mut flat, array_arg("flatten", &args, 0), depth);
+    JsonPP::Array(flat)
+}
+
+pub(crate) fn zip_impl(args: Vec<JsonPP>) -> JsonPP {
+    assert!(!args.is_empty(), "'zip' needs at least one array");
+    let arrays: Vec<Vec<JsonPP>> = (0..args.len())
+        .map(|index| array_arg("zip", &args, index))
+        .collect();
+
+    // Stops at the end of the shortest array
+    let len = arrays.iter().map(Vec::len).min().unwrap();
+    JsonPP::Array(
+        (0..len)
+            .map(|index| JsonPP::Array(arrays.iter().map(|arr| arr[index].clone()).collect()))
+            .collect(),
+    )
+}
+
+pub(crate) fn enumerate_impl(args: Vec<JsonPP>) -> JsonPP {
+    assert_eq!(args.len(), 1);
+    JsonPP::Array(
+        array_arg("enumerate", &args, 0)
+            .into_iter()
+            .enumerate()
+            .map(|(index, el)| JsonPP::Array(vec![JsonPP::Int(index as i64), el]))
+            .collect(),
+    )
+}
+
+pub(crate) fn slice_impl(args: Vec<JsonPP>) -> JsonPP {
+    assert!((2..=4).contains(&args.len()));
+    let arr = array_arg("slice", &args, 0);
+    let start = optional_int_arg("slice", &args, 1);
+    let end = optional_int_arg("slice", &args, 2);
+    let step = optional_int_arg("slice", &args, 3);
+    if step == Some(0) {
+        panic!("'slice' step can't be zero");
+    }
+
+    JsonPP::Array(
+        slice_indices(arr.len(), start, end, step)
+            .into_iter()
+            .map(|index| arr[index].clone())
+            .collect(),
+    )
+}
+
+fn count_arg(fun: &str, args: &[JsonPP], index: usize) -> usize {
+    let count = int_arg(fun, args, index);
+    if count < 0 {
+        panic!("'{}' expects a count of at least 0, found {}", fun, count);
+    }
+    count as usize
+}
+
+pub(crate) fn take_impl(args: Vec<JsonPP>) -> JsonPP {
+    assert_eq!(args.len(), 2);
+    let count = count_arg("take", &args, 1);
+    JsonPP::Array(
+        array_arg("take", &args, 0)
+            .into_iter()
+            .take(count)
+            .collect(),
+    )
+}
+
+pub(crate) fn drop_impl(args: Vec<JsonPP>) -> JsonPP {
+    assert_eq!(args.len(), 2);
+    let count = count_arg("drop", &args, 1);
+    JsonPP::Array(
+        array_arg("drop", &args, 0)
+            .into_iter()
+            .skip(count)
+            .collect(),
+    )
+}
+
+pub(crate) fn index_of_impl(args: Vec<JsonPP>) -> JsonPP {
+    assert_eq!(args.len(), 2);
+
+    // -1 when not found
+    let index = if let JsonPP::Array(arr) = &args[0] {
+        arr.iter().position(|el| *el == args[1])
+    } else {
+        let string = string_arg("index_of", &args, 0);
+        let needle = string_arg("index_of", &args, 1);
+        string
+            .find(&needle)
+            .map(|byte_index| string[..byte_index].chars().count())
+    };
+
+    JsonPP::Int(index.map_or(-1, |index| index as i64))
+}
+
 pub(crate) fn values_impl(args: Vec<JsonPP>) -> JsonPP {
     assert_eq!(args.len(), 1);
     let JsonPP::Object(obj) = args[0].clone() else {
@@ -496,6 +2583,171 @@ pub(crate) fn keys_impl(args: Vec<JsonPP>) -> JsonPP {
     )
 }
 
+type Entries = Vec<(String, JsonPP)>;
+
+fn sorted_entries(obj: HashMap<String, JsonPP>) -> Entries {
+    // Sorted so that the order of the output doesn't depend on the hash map
+    let mut entries: Entries = obj.into_iter().collect();
+    entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+    entries
+}
+
+pub(crate) fn entries_impl(args: Vec<JsonPP>) -> JsonPP {
+    assert_eq!(args.len(), 1);
+    JsonPP::Array(
+        sorted_entries(object_arg("entries", &args, 0))
+            .into_iter()
+            .map(|(key, val)| JsonPP::Array(vec![JsonPP::String(key), val]))
+            .collect(),
+    )
+}
+
+fn entry_pair(fun: &str, entry: JsonPP) -> (String, JsonPP) {
+    let JsonPP::Array(pair) = entry else {
+        panic!("'{}' expects [key, value] pairs, found {:?}", fun, entry);
+    };
+    let Ok([key, val]) = <[JsonPP; 2]>::try_from(pair) else {
+        panic!("'{}' expects [key, value] pairs with two elements", fun);
+    };
+
+    (group_key(fun, key), val)
+}
+
+pub(crate) fn from_entries_impl(args: Vec<JsonPP>) -> JsonPP {
+    assert_eq!(args.len(), 1);
+    JsonPP::Object(
+        array_arg("from_entries", &args, 0)
+            .into_iter()
+            .map(|entry| entry_pair("from_entries", entry))
+            .collect(),
+    )
+}
+
+fn key_list(fun: &str, args: &[JsonPP]) -> Vec<String> {
+    // Keys can be given as separate arguments or as one array
+    let keys = match args {
+        [JsonPP::Array(keys)] => keys.clone(),
+        keys => keys.to_vec(),
+    };
+
+    keys.into_iter()
+        .map(|key| {
+            let JsonPP::String(key) = key else {
+                panic!("'{}' expects keys to be strings, found {:?}", fun, key);
+            };
+            key
+        })
+        .collect()
+}
+
+pub(crate) fn pick_impl(args: Vec<JsonPP>) -> JsonPP {
+    assert!(!args.is_empty());
+    let mut obj = object_arg("pick", &args, 0);
+    let keys = key_list("pick", &args[1..]);
+
+    // Missing keys are skipped
+    JsonPP::Object(
+        keys.into_iter()
+            .filter_map(|key| obj.remove_entry(&key))
+            .collect(),
+    )
+}
+
+pub(crate) fn omit_impl(args: Vec<JsonPP>) -> JsonPP {
+    assert!(!args.is_empty());
+    let mut obj = object_arg("omit", &args, 0);
+    for key in key_list("omit", &args[1..]) {
+        obj.remove(&key);
+    }
+    JsonPP::Object(obj)
+}
+
+pub(crate) fn rename_keys_impl(args: Vec<JsonPP>) -> JsonPP {
+    assert_eq!(args.len(), 2);
+    let obj = object_arg("rename_keys", &args, 0);
+    let renames = object_arg("rename_keys", &args, 1);
+
+    let mut renamed = HashMap::new();
+    let mut kept = HashMap::new();
+    for (key, val) in obj {
+        match renames.get(&key) {
+            Some(JsonPP::String(new_key)) => {
+                renamed.insert(new_key.clone(), val);
+            }
+            Some(other) => panic!(
+                "'rename_keys' expects new names to be strings, found {:?}",
+                other
+            ),
+            None => {
+                kept.insert(key, val);
+            }
+        }
+    }
+
+    // Renamed keys replace existing ones with the same name
+    kept.extend(renamed);
+    JsonPP::Object(kept)
+}
+
+pub(crate) fn call_for_each_entry(fun: &str, args: Vec<JsonPP>, with_values: bool) -> JsonPP {
+    assert!(
+        args.len() == 2,
+        "'{}' takes a function and an object, found {} arguments",
+        fun,
+        args.len()
+    );
+    let calls = sorted_entries(object_arg(fun, &args, 1))
+        .into_iter()
+        .map(|(key, val)| {
+            let mut call_args = vec![args[0].clone(), JsonPP::String(key)];
+            if with_values {
+                call_args.push(val);
+            }
+            call_args
+        });
+    expand_calls(fun, &args, calls.collect())
+}
+
+fn object_with_results(fun: &str, args: &[JsonPP]) -> (Entries, Vec<JsonPP>) {
+    assert_eq!(args.len(), 3);
+    let entries = sorted_entries(object_arg(fun, args, 1));
+    let results = array_arg(fun, args, 2);
+    assert_eq!(entries.len(), results.len());
+    (entries, results)
+}
+
+pub(crate) fn map_keys_impl(args: Vec<JsonPP>) -> JsonPP {
+    let (entries, keys) = object_with_results("map_keys", &args);
+
+    JsonPP::Object(
+        keys.into_iter()
+            .map(|key| group_key("map_keys", key))
+            .zip(entries.into_iter().map(|(_, val)| val))
+            .collect(),
+    )
+}
+
+pub(crate) fn map_entries_impl(args: Vec<JsonPP>) -> JsonPP {
+    let (_, pairs) = object_with_results("map_entries", &args);
+
+    JsonPP::Object(
+        pairs
+            .into_iter()
+            .map(|pair| entry_pair("map_entries", pair))
+            .collect(),
+    )
+}
+
+pub(crate) fn invert_impl(args: Vec<JsonPP>) -> JsonPP {
+    assert_eq!(args.len(), 1);
+    JsonPP::Object(
+        sorted_entries(object_arg("invert", &args, 0))
+            .into_iter()
+            .map(|(key, val)| (group_key("invert", val), JsonPP::String(key)))
+            .collect(),
+    )
+}
+
 pub(crate) fn shell_impl(args: Vec<JsonPP>) -> JsonPP {
     assert!(!args.is_empty());
 
@@ -4,7 +4,10 @@ use log::debug;
 
 use crate::{
     jsonpp::{Definition, Dynamic, JsonPP},
-    paths::{make_absolute, ref_chain, PathChunk},
+    paths::{
+        array_index, format_path, has_wildcards, make_absolute, ref_chain, relative_json_pointer,
+        slice_indices, PathChunk,
+    },
 };
 
 pub(crate) fn evaluate_raw(parsed: JsonPP) -> JsonPP {
@@ -21,6 +24,13 @@ pub(crate) fn evaluate_raw(parsed: JsonPP) -> JsonPP {
 
             let dyn_deps = dyn_val.dependencies.iter().filter(|dep| {
                 let path = make_absolute(dyn_path, dep);
+                if has_wildcards(&path) {
+                    // Wait on every match, there may be none
+                    return abs_fetch_all(&path, &root, Some(dyn_path))
+                        .into_iter()
+                        .any(contains_dynamics);
+                }
+
                 // We should also check the contents of the dependencies if they have them
 
                 if let Some(target) = abs_fetch(&path, &root) {
@@ -29,7 +39,7 @@ pub(crate) fn evaluate_raw(parsed: JsonPP) -> JsonPP {
                     // Target cannot be fetched
                     // We must be in a ref, otherwise this is a bug in jsonpp
                     assert!(
-                        dyn_val.is_ref(),
+                        dyn_val.is_ref() || dyn_val.is_ref_or(),
                         "JsonPP is bugged and is referencing something that doesn't exist"
                     );
                     // We are referencing something that does not exist
@@ -42,18 +52,28 @@ pub(crate) fn evaluate_raw(parsed: JsonPP) -> JsonPP {
                         if let Some(nearest_container) = abs_fetch(&temp_path, &root) {
                             if matches!(nearest_container, JsonPP::Dynamic(_)) {
                                 return true;
+                            } else if dyn_val.is_ref_or() {
+                                // It never will, so ref_or can fall back to the default
+                                return false;
                             } else {
                                 // End of the path is wrong
-                                dbg!(path);
-                                panic!("You are referencing something that doesn't exist");
+                                panic!(
+                                    "You are referencing something that doesn't exist: '{}'",
+                                    format_path(&path)
+                                );
                             }
                         } else {
                             temp_path.pop();
                         }
                     }
                     // Root of the path is wrong
-                    dbg!(path);
-                    panic!("You are referencing something that doesn't exist");
+                    if dyn_val.is_ref_or() {
+                        return false;
+                    }
+                    panic!(
+                        "You are referencing something that doesn't exist: '{}'",
+                        format_path(&path)
+                    );
                 }
             });
 
@@ -80,14 +100,24 @@ pub(crate) fn evaluate_raw(parsed: JsonPP) -> JsonPP {
     root
 }
 
-pub(crate) fn evaluate(parsed: JsonPP) -> serde_json::Value {
+pub(crate) fn evaluate_to_string(parsed: JsonPP) -> String {
     let root = evaluate_raw(parsed);
 
-    let Ok(out) = root.clone().try_into() else {
-        panic!("No dynamics left and still can't make it into serde_json::Value");
-    };
+    match root.to_json_string(true) {
+        Ok(out) => out,
+        Err(value) if value.is_stripped() => panic!("Can't convert {:?} to json", value),
+        Err(_) => panic!("No dynamics left and still can't make it into json"),
+    }
+}
+
+pub(crate) fn evaluate(parsed: JsonPP) -> serde_json::Value {
+    let root = evaluate_raw(parsed);
 
-    out
+    match root.try_into() {
+        Ok(out) => out,
+        Err(value) if value.is_stripped() => panic!("Can't convert {:?} to json", value),
+        Err(_) => panic!("No dynamics left and still can't make it into serde_json::Value"),
+    }
 }
 
 fn preprocess(
@@ -109,7 +139,8 @@ fn preprocess(
                     temp_path.push(PathChunk::Argument(index));
                     let inner = preprocess(dyn_paths, temp_path.clone(), arg.to_owned());
 
-                    if matches!(inner, JsonPP::Dynamic(_)) {
+                    // Arrays and objects given as arguments may have dynamics inside
+                    if contains_dynamics(&inner) {
                         dyn_val
                             .dependencies
                             .push(vec![PathChunk::Parent, PathChunk::Argument(index)]);
@@ -119,10 +150,13 @@ fn preprocess(
                 })
                 .collect();
 
-            if dyn_val.is_ref() {
+            if dyn_val.is_ref() || dyn_val.is_ref_or() {
                 match dyn_val.args[1].clone() {
                     JsonPP::String(string) => {
-                        dyn_val.dependencies.push(ref_chain(string));
+                        // Name lookups only depend on the path, not the value behind it
+                        if !matches!(relative_json_pointer(&string), Some((_, "#"))) {
+                            dyn_val.dependencies.push(ref_chain(string));
+                        }
                     }
                     JsonPP::Dynamic(_) => {}
                     other => panic!("Trying to call ref on {:?}", other),
@@ -198,9 +232,43 @@ fn insert(path: &[PathChunk], root: &mut JsonPP, value: JsonPP) {
 
             insert(rest, &mut inner.args[*index], value)
         }
+        PathChunk::IndexFromEnd(_)
+        | PathChunk::Wildcard
+        | PathChunk::IndexWildcard
+        | PathChunk::Descendants
+        | PathChunk::Slice { .. } => {
+            panic!("You are not supposed to have a wildcard in the path when inserting")
+        }
     }
 }
 
+/// Indexes of the array elements that will be in the output, which negative indexes and
+/// slices count from
+fn kept_indices(inner: &[JsonPP]) -> Vec<usize> {
+    (0..inner.len())
+        .filter(|index| inner[*index] != JsonPP::Undefined)
+        .collect()
+}
+
+/// Resolves a negative index against the elements that will be in the output, or gives the
+/// last element still being computed that could change which one that is
+fn index_from_end(inner: &[JsonPP], from_end: usize) -> Option<usize> {
+    if from_end == 0 {
+        return None;
+    }
+
+    let mut remaining = from_end;
+    for index in (0..inner.len()).rev() {
+        match &inner[index] {
+            JsonPP::Undefined => continue,
+            JsonPP::Dynamic(_) => return Some(index),
+            _ if remaining == 1 => return Some(index),
+            _ => remaining -= 1,
+        }
+    }
+    None
+}
+
 pub(crate) fn abs_fetch<'a>(path: &[PathChunk], root: &'a JsonPP) -> Option<&'a JsonPP> {
     if path.is_empty() {
         return Some(root);
@@ -211,14 +279,17 @@ pub(crate) fn abs_fetch<'a>(path: &[PathChunk], root: &'a JsonPP) -> Option<&'a
 
     match next {
         PathChunk::Parent => panic!("Absolute path fetching needs an absolute path"),
-        PathChunk::Key(key) => {
-            let JsonPP::Object(inner) = root else {
+        PathChunk::Key(key) => match (root, array_index(key)) {
+            (JsonPP::Object(inner), _) => inner.get(key).map(|target| abs_fetch(rest, target))?,
+            // JSON Pointers can't tell keys and indices apart, so numeric keys also index arrays
+            (JsonPP::Array(inner), Some(index)) => {
+                inner.get(index).map(|target| abs_fetch(rest, target))?
+            }
+            _ => {
                 debug!("Accessing with a key: {:?}, {:?}, {:?}", root, key, path);
-                return None;
-            };
-
-            inner.get(key).map(|target| abs_fetch(rest, target))?
-        }
+                None
+            }
+        },
         PathChunk::Index(index) => {
             let JsonPP::Array(inner) = root else {
                 debug!(
@@ -230,6 +301,18 @@ pub(crate) fn abs_fetch<'a>(path: &[PathChunk], root: &'a JsonPP) -> Option<&'a
 
             inner.get(*index).map(|target| abs_fetch(rest, target))?
         }
+        PathChunk::IndexFromEnd(from_end) => {
+            let JsonPP::Array(inner) = root else {
+                debug!(
+                    "Accessing with an index: {:?}, -{:?}, {:?}",
+                    root, from_end, path
+                );
+                return None;
+            };
+
+            let index = index_from_end(inner, *from_end)?;
+            abs_fetch(rest, &inner[index])
+        }
         PathChunk::Argument(index) => {
             let JsonPP::Dynamic(inner) = root else {
                 debug!(
@@ -244,6 +327,192 @@ pub(crate) fn abs_fetch<'a>(path: &[PathChunk], root: &'a JsonPP) -> Option<&'a
                 .get(*index)
                 .map(|target| abs_fetch(rest, target))?
         }
+        PathChunk::Wildcard
+        | PathChunk::IndexWildcard
+        | PathChunk::Descendants
+        | PathChunk::Slice { .. } => {
+            panic!("Wildcard paths can match several values, use abs_fetch_all")
+        }
+    }
+}
+
+pub(crate) fn abs_fetch_all<'a>(
+    path: &[PathChunk],
+    root: &'a JsonPP,
+    self_path: Option<&[PathChunk]>,
+) -> Vec<&'a JsonPP> {
+    // Fetches everything a wildcard path matches, in document order with object keys sorted
+    // The ref itself and its ancestors are never matched, that would be a reference cycle
+    // Dynamics blocking the way are returned as is, so that dependants can wait for them
+    let mut matcher = Matcher {
+        root,
+        probing: self_path.map(<[PathChunk]>::to_vec).into_iter().collect(),
+    };
+    let mut found = vec![];
+    matcher.fetch_matches(path, root, &mut vec![], self_path, &mut found);
+    found
+}
+
+struct Matcher<'r> {
+    root: &'r JsonPP,
+    // Wildcard refs whose matches are being looked into, to not go around in circles
+    probing: Vec<Vec<PathChunk>>,
+}
+
+impl Matcher<'_> {
+    fn fetch_matches<'a>(
+        &mut self,
+        path: &[PathChunk],
+        node: &'a JsonPP,
+        node_path: &mut Vec<PathChunk>,
+        self_path: Option<&[PathChunk]>,
+        found: &mut Vec<&'a JsonPP>,
+    ) {
+        if self_path.is_some_and(|self_path| node_path.starts_with(self_path)) {
+            return;
+        }
+
+        let is_ancestor = self_path.is_some_and(|self_path| self_path.starts_with(node_path));
+        let blocked = match node {
+            JsonPP::Dynamic(inner) if !path.is_empty() => {
+                if matches!(path[0], PathChunk::Argument(_)) {
+                    false
+                } else if self.could_match_inside(inner, path) {
+                    true
+                } else {
+                    // Nothing in the value it resolves to can match, no need to wait for it
+                    return;
+                }
+            }
+            _ => false,
+        };
+
+        if path.is_empty() || blocked {
+            if !is_ancestor {
+                found.push(node);
+            }
+            return;
+        }
+
+        let rest = &path[1..];
+        let matching_children = match &path[0] {
+            PathChunk::Parent => panic!("Absolute path fetching needs an absolute path"),
+            PathChunk::Wildcard => children(node),
+            PathChunk::IndexWildcard if matches!(node, JsonPP::Array(_)) => children(node),
+            PathChunk::IndexWildcard => vec![],
+            PathChunk::Slice { start, end, step } => match node {
+                JsonPP::Array(inner) => {
+                    match inner.iter().position(|el| matches!(el, JsonPP::Dynamic(_))) {
+                        // Which elements make it into the output isn't known yet
+                        Some(pending) => vec![(PathChunk::Index(pending), &inner[pending])],
+                        None => {
+                            let kept = kept_indices(inner);
+                            slice_indices(kept.len(), *start, *end, *step)
+                                .into_iter()
+                                .map(|index| (PathChunk::Index(kept[index]), &inner[kept[index]]))
+                                .collect()
+                        }
+                    }
+                }
+                _ => vec![],
+            },
+            PathChunk::Descendants => {
+                // Zero levels deep, then recurse with the same path to go deeper
+                self.fetch_matches(rest, node, node_path, self_path, found);
+                for (chunk, child) in children(node) {
+                    node_path.push(chunk);
+                    self.fetch_matches(path, child, node_path, self_path, found);
+                    node_path.pop();
+                }
+                return;
+            }
+            chunk => abs_fetch(std::slice::from_ref(chunk), node)
+                .map(|child| match (chunk, node) {
+                    // Keep the path concrete when a numeric key indexed into an array
+                    (PathChunk::Key(key), JsonPP::Array(_)) => {
+                        (PathChunk::Index(array_index(key).unwrap()), child)
+                    }
+                    (PathChunk::IndexFromEnd(from_end), JsonPP::Array(inner)) => (
+                        PathChunk::Index(index_from_end(inner, *from_end).unwrap()),
+                        child,
+                    ),
+                    _ => (chunk.clone(), child),
+                })
+                .into_iter()
+                .collect(),
+        };
+
+        for (chunk, child) in matching_children {
+            node_path.push(chunk);
+            self.fetch_matches(rest, child, node_path, self_path, found);
+            node_path.pop();
+        }
+    }
+
+    fn could_match_inside(&mut self, dynamic: &Dynamic, path: &[PathChunk]) -> bool {
+        if self
+            .probing
+            .iter()
+            .any(|probing| probing.starts_with(&dynamic.path))
+        {
+            // Waits on a ref already being looked into further up, which can't match
+            // itself or what it is inside of
+            return false;
+        }
+
+        // Most dynamics could become anything, but a wildcard ref becomes an array of
+        // values that are already in the document, so those can be looked into right away
+        let Some(target) = wildcard_target(dynamic) else {
+            return true;
+        };
+
+        self.probing.push(dynamic.path.clone());
+        let mut matches = vec![];
+        let root = self.root;
+        self.fetch_matches(
+            &target,
+            root,
+            &mut vec![],
+            Some(&dynamic.path),
+            &mut matches,
+        );
+        let value = JsonPP::Array(matches.into_iter().cloned().collect());
+
+        let mut inside = vec![];
+        self.fetch_matches(path, &value, &mut vec![], None, &mut inside);
+        self.probing.pop();
+
+        !inside.is_empty()
+    }
+}
+
+fn wildcard_target(dynamic: &Dynamic) -> Option<Vec<PathChunk>> {
+    // Refs with extra arguments can point into them, those aren't in the document
+    let plain = (dynamic.is_ref() && dynamic.args.len() == 2)
+        || (dynamic.is_ref_or() && dynamic.args.len() == 3);
+    let Some(JsonPP::String(target)) = dynamic.args.get(1).filter(|_| plain) else {
+        return None;
+    };
+
+    let target_path = make_absolute(&dynamic.path, &ref_chain(target.to_owned()));
+    (has_wildcards(&target_path) && relative_json_pointer(target).is_none()).then_some(target_path)
+}
+
+fn children(node: &JsonPP) -> Vec<(PathChunk, &JsonPP)> {
+    match node {
+        JsonPP::Object(inner) => {
+            let mut keys: Vec<&String> = inner.keys().collect();
+            keys.sort();
+            keys.into_iter()
+                .map(|key| (PathChunk::Key(key.to_owned()), &inner[key]))
+                .collect()
+        }
+        JsonPP::Array(inner) => inner
+            .iter()
+            .enumerate()
+            .map(|(index, child)| (PathChunk::Index(index), child))
+            .collect(),
+        _ => vec![],
     }
 }
 
@@ -301,7 +570,9 @@ fn contains_dynamics(target: &JsonPP) -> bool {
         | JsonPP::Bool(_)
         | JsonPP::String(_)
         | JsonPP::Int(_)
-        | JsonPP::Float(_) => false,
+        | JsonPP::Float(_)
+        | JsonPP::BigInt(_)
+        | JsonPP::Decimal(_) => false,
 
         JsonPP::Array(contents) => contents.iter().any(contains_dynamics),
         JsonPP::Object(hash_map) => hash_map.values().any(contains_dynamics),
@@ -0,0 +1,740 @@
+use std::cmp::Ordering;
+
+use regex::Regex;
+
+use crate::{builtins::num_ordering, jsonpp::JsonPP, paths::slice_indices};
+
+// JSONPath as described in RFC 9535
+
+pub(crate) fn query<'a>(path: &str, root: &'a JsonPP) -> Vec<&'a JsonPP> {
+    let mut parser = Parser {
+        chars: path.chars().collect(),
+        pos: 0,
+        source: path,
+    };
+
+    let parsed = parser.root_query();
+    if parser.pos != parser.chars.len() {
+        parser.fail("trailing characters");
+    }
+
+    parsed.select(root, root)
+}
+
+#[derive(Debug)]
+struct Query {
+    relative: bool, // Starts from @ instead of $
+    segments: Vec<Segment>,
+}
+
+#[derive(Debug)]
+enum Segment {
+    Child(Vec<Selector>),
+    Descendant(Vec<Selector>),
+}
+
+#[derive(Debug)]
+enum Selector {
+    Name(String),
+    Index(i64),
+    Slice(Option<i64>, Option<i64>, Option<i64>),
+    Wildcard,
+    Filter(Expression),
+}
+
+#[derive(Debug)]
+enum Expression {
+    Or(Vec<Expression>),
+    And(Vec<Expression>),
+    Not(Box<Expression>),
+    Exists(Query),
+    Compare(Comparable, Comparison, Comparable),
+    Test(Comparable), // Functions like match that are true or false on their own
+}
+
+#[derive(Debug)]
+enum Comparable {
+    Literal(JsonPP),
+    Query(Query),
+    Function(String, Vec<Comparable>),
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Comparison {
+    Eq,
+    NotEq,
+    Lt,
+    Lte,
+    Gt,
+    Gte,
+}
+
+impl Query {
+    fn select<'a>(&self, current: &'a JsonPP, root: &'a JsonPP) -> Vec<&'a JsonPP> {
+        let start = if self.relative { current } else { root };
+
+        self.segments.iter().fold(vec![start], |nodes, segment| {
+            nodes
+                .into_iter()
+                .flat_map(|node| segment.select(node, root))
+                .collect()
+        })
+    }
+
+    fn singular<'a>(&self, current: &'a JsonPP, root: &'a JsonPP) -> Option<&'a JsonPP> {
+        let nodes = self.select(current, root);
+        if nodes.len() == 1 {
+            Some(nodes[0])
+        } else {
+            None
+        }
+    }
+}
+
+impl Segment {
+    fn select<'a>(&self, node: &'a JsonPP, root: &'a JsonPP) -> Vec<&'a JsonPP> {
+        match self {
+            Segment::Child(selectors) => selectors
+                .iter()
+                .flat_map(|selector| selector.select(node, root))
+                .collect(),
+            Segment::Descendant(selectors) => descendants(node)
+                .into_iter()
+                .flat_map(|descendant| {
+                    selectors
+                        .iter()
+                        .flat_map(|selector| selector.select(descendant, root))
+                        .collect::<Vec<_>>()
+                })
+                .collect(),
+        }
+    }
+}
+
+impl Selector {
+    fn select<'a>(&self, node: &'a JsonPP, root: &'a JsonPP) -> Vec<&'a JsonPP> {
+        match (self, node) {
+            (Selector::Name(name), JsonPP::Object(inner)) => inner.get(name).into_iter().collect(),
+            (Selector::Index(index), JsonPP::Array(inner)) => {
+                let absolute = if *index < 0 {
+                    inner.len() as i64 + index
+                } else {
+                    *index
+                };
+
+                usize::try_from(absolute)
+                    .ok()
+                    .and_then(|absolute| inner.get(absolute))
+                    .into_iter()
+                    .collect()
+            }
+            (Selector::Slice(start, end, step), JsonPP::Array(inner)) => {
+                slice_indices(inner.len(), *start, *end, *step)
+                    .into_iter()
+                    .map(|index| &inner[index])
+                    .collect()
+            }
+            (Selector::Wildcard, _) => children(node),
+            (Selector::Filter(expression), _) => children(node)
+                .into_iter()
+                .filter(|child| expression.test(child, root))
+                .collect(),
+            _ => vec![],
+        }
+    }
+}
+
+impl Expression {
+    fn test(&self, current: &JsonPP, root: &JsonPP) -> bool {
+        match self {
+            Expression::Or(inner) => inner.iter().any(|expr| expr.test(current, root)),
+            Expression::And(inner) => inner.iter().all(|expr| expr.test(current, root)),
+            Expression::Not(inner) => !inner.test(current, root),
+            Expression::Exists(query) => !query.select(current, root).is_empty(),
+            Expression::Compare(first, comparison, second) => compare(
+                first.evaluate(current, root),
+                *comparison,
+                second.evaluate(current, root),
+            ),
+            Expression::Test(function) => {
+                function.evaluate(current, root) == Some(JsonPP::Bool(true))
+            }
+        }
+    }
+}
+
+impl Comparable {
+    fn evaluate(&self, current: &JsonPP, root: &JsonPP) -> Option<JsonPP> {
+        // None is the absence of a value, which is different from null
+        match self {
+            Comparable::Literal(value) => Some(value.clone()),
+            Comparable::Query(query) => query.singular(current, root).cloned(),
+            Comparable::Function(name, args) => match (name.as_str(), args.as_slice()) {
+                ("length", [arg]) => match arg.evaluate(current, root)? {
+                    JsonPP::String(inner) => Some(JsonPP::Int(inner.chars().count() as i64)),
+                    JsonPP::Array(inner) => Some(JsonPP::Int(inner.len() as i64)),
+                    JsonPP::Object(inner) => Some(JsonPP::Int(inner.len() as i64)),
+                    _ => None,
+                },
+                ("count", [Comparable::Query(query)]) => {
+                    Some(JsonPP::Int(query.select(current, root).len() as i64))
+                }
+                ("value", [Comparable::Query(query)]) => query.singular(current, root).cloned(),
+                ("match" | "search", [string, pattern]) => {
+                    let matched = match (
+                        string.evaluate(current, root),
+                        pattern.evaluate(current, root),
+                    ) {
+                        (Some(JsonPP::String(string)), Some(JsonPP::String(pattern))) => {
+                            i_regexp(&pattern, name == "match")
+                                .is_some_and(|regex| regex.is_match(&string))
+                        }
+                        // Anything but strings is just not a match
+                        _ => false,
+                    };
+                    Some(JsonPP::Bool(matched))
+                }
+                _ => panic!("Unsupported JSONPath function call: {}({:?})", name, args),
+            },
+        }
+    }
+}
+
+fn i_regexp(pattern: &str, whole: bool) -> Option<Regex> {
+    // RFC 9485 I-Regexp, which is close enough to the regex crate syntax,
+    // except that a dot doesn't match carriage returns either
+    let mut translated = String::new();
+    let mut chars = pattern.chars();
+    let mut in_class = false;
+    while let Some(ch) = chars.next() {
+        match ch {
+            '\\' => {
+                translated.push(ch);
+                translated.extend(chars.next());
+            }
+            '[' => {
+                in_class = true;
+                translated.push(ch);
+            }
+            ']' => {
+                in_class = false;
+                translated.push(ch);
+            }
+            '.' if !in_class => translated.push_str("[^\\n\\r]"),
+            _ => translated.push(ch),
+        }
+    }
+
+    // Match is for the whole string, search for any part of it
+    if whole {
+        translated = format!("^(?:{})$", translated);
+    }
+
+    // Invalid patterns don't match anything
+    Regex::new(&translated).ok()
+}
+
+fn compare(first: Option<JsonPP>, comparison: Comparison, second: Option<JsonPP>) -> bool {
+    let equal = || match (&first, &second) {
+        (None, None) => true,
+        (Some(first), Some(second)) => {
+            order(first, second) == Some(Ordering::Equal) || first == second
+        }
+        _ => false,
+    };
+    let less = |first: &Option<JsonPP>, second: &Option<JsonPP>| match (first, second) {
+        (Some(first), Some(second)) => order(first, second) == Some(Ordering::Less),
+        _ => false,
+    };
+
+    match comparison {
+        Comparison::Eq => equal(),
+        Comparison::NotEq => !equal(),
+        Comparison::Lt => less(&first, &second),
+        Comparison::Lte => less(&first, &second) || equal(),
+        Comparison::Gt => less(&second, &first),
+        Comparison::Gte => less(&second, &first) || equal(),
+    }
+}
+
+fn order(first: &JsonPP, second: &JsonPP) -> Option<Ordering> {
+    // Only numbers and strings have an order
+    match (first, second) {
+        (JsonPP::String(first), JsonPP::String(second)) => Some(first.cmp(second)),
+        _ => num_ordering(first, second),
+    }
+}
+
+fn children(node: &JsonPP) -> Vec<&JsonPP> {
+    match node {
+        JsonPP::Array(inner) => inner.iter().collect(),
+        JsonPP::Object(inner) => {
+            // Sorted, so that the output is the same on every run
+            let mut keys: Vec<&String> = inner.keys().collect();
+            keys.sort();
+            keys.into_iter().map(|key| &inner[key]).collect()
+        }
+        _ => vec![],
+    }
+}
+
+fn descendants(node: &JsonPP) -> Vec<&JsonPP> {
+    // The node itself and everything under it, parents before children
+    let mut out = vec![node];
+    for child in children(node) {
+        out.extend(descendants(child));
+    }
+    out
+}
+
+struct Parser<'a> {
+    chars: Vec<char>,
+    pos: usize,
+    source: &'a str,
+}
+
+impl Parser<'_> {
+    fn fail(&self, reason: &str) -> ! {
+        panic!(
+            "Invalid JSONPath '{}' at position {}: {}",
+            self.source, self.pos, reason
+        )
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let next = self.peek();
+        self.pos += 1;
+        next
+    }
+
+    fn eat(&mut self, expected: &str) -> bool {
+        // Consumes the expected string if it comes next
+        let matches = expected
+            .chars()
+            .enumerate()
+            .all(|(offset, ch)| self.chars.get(self.pos + offset) == Some(&ch));
+
+        if matches {
+            self.pos += expected.chars().count();
+        }
+        matches
+    }
+
+    fn expect(&mut self, expected: &str) {
+        if !self.eat(expected) {
+            self.fail(&format!("expected '{}'", expected));
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().is_some_and(|ch| ch.is_whitespace()) {
+            self.pos += 1;
+        }
+    }
+
+    fn root_query(&mut self) -> Query {
+        self.expect("$");
+        Query {
+            relative: false,
+            segments: self.segments(),
+        }
+    }
+
+    fn segments(&mut self) -> Vec<Segment> {
+        let mut segments = vec![];
+
+        loop {
+            if self.eat("..") {
+                let selectors = match self.peek() {
+                    Some('[') => self.bracket(),
+                    Some('*') => {
+                        self.pos += 1;
+                        vec![Selector::Wildcard]
+                    }
+                    _ => vec![Selector::Name(self.member_name())],
+                };
+                segments.push(Segment::Descendant(selectors));
+            } else if self.eat(".") {
+                if self.eat("*") {
+                    segments.push(Segment::Child(vec![Selector::Wildcard]));
+                } else {
+                    segments.push(Segment::Child(vec![Selector::Name(self.member_name())]));
+                }
+            } else if self.peek() == Some('[') {
+                segments.push(Segment::Child(self.bracket()));
+            } else {
+                return segments;
+            }
+        }
+    }
+
+    fn member_name(&mut self) -> String {
+        let start = self.pos;
+        while self
+            .peek()
+            .is_some_and(|ch| ch == '_' || ch.is_alphanumeric() || !ch.is_ascii())
+        {
+            self.pos += 1;
+        }
+
+        if start == self.pos || self.chars[start].is_ascii_digit() {
+            self.fail("expected a member name");
+        }
+
+        self.chars[start..self.pos].iter().collect()
+    }
+
+    fn bracket(&mut self) -> Vec<Selector> {
+        self.expect("[");
+        let mut selectors = vec![];
+
+        loop {
+            self.skip_whitespace();
+            selectors.push(self.selector());
+            self.skip_whitespace();
+
+            if self.eat("]") {
+                return selectors;
+            }
+            self.expect(",");
+        }
+    }
+
+    fn selector(&mut self) -> Selector {
+        match self.peek() {
+            Some('\'' | '"') => Selector::Name(self.string_literal()),
+            Some('*') => {
+                self.pos += 1;
+                Selector::Wildcard
+            }
+            Some('?') => {
+                self.pos += 1;
+                Selector::Filter(self.logical_or())
+            }
+            _ => {
+                let start = self.optional_int();
+                self.skip_whitespace();
+                if !self.eat(":") {
+                    return Selector::Index(
+                        start.unwrap_or_else(|| self.fail("expected a selector")),
+                    );
+                }
+
+                self.skip_whitespace();
+                let end = self.optional_int();
+                self.skip_whitespace();
+                let step = if self.eat(":") {
+                    self.skip_whitespace();
+                    self.optional_int()
+                } else {
+                    None
+                };
+
+                if step == Some(0) {
+                    // RFC says a zero step selects nothing
+                    return Selector::Slice(Some(0), Some(0), None);
+                }
+                Selector::Slice(start, end, step)
+            }
+        }
+    }
+
+    fn optional_int(&mut self) -> Option<i64> {
+        let start = self.pos;
+        self.eat("-");
+        while self.peek().is_some_and(|ch| ch.is_ascii_digit()) {
+            self.pos += 1;
+        }
+
+        if self.pos == start {
+            return None;
+        }
+
+        let digits: String = self.chars[start..self.pos].iter().collect();
+        Some(
+            digits
+                .parse()
+                .unwrap_or_else(|_| self.fail(&format!("invalid integer '{}'", digits))),
+        )
+    }
+
+    fn string_literal(&mut self) -> String {
+        let quote = self.next().unwrap();
+        let mut out = String::new();
+
+        loop {
+            match self.next() {
+                Some(ch) if ch == quote => return out,
+                Some('\\') => out.push(match self.next() {
+                    Some('b') => '\u{8}',
+                    Some('f') => '\u{c}',
+                    Some('n') => '\n',
+                    Some('r') => '\r',
+                    Some('t') => '\t',
+                    Some('u') => {
+                        let hex: String = (0..4).filter_map(|_| self.next()).collect();
+                        u32::from_str_radix(&hex, 16)
+                            .ok()
+                            .and_then(char::from_u32)
+                            .unwrap_or_else(|| self.fail("invalid unicode escape"))
+                    }
+                    Some(ch @ ('\'' | '"' | '\\' | '/')) => ch,
+                    _ => self.fail("invalid escape"),
+                }),
+                Some(ch) => out.push(ch),
+                None => self.fail("unterminated string"),
+            }
+        }
+    }
+
+    fn logical_or(&mut self) -> Expression {
+        let mut inner = vec![self.logical_and()];
+        loop {
+            self.skip_whitespace();
+            if !self.eat("||") {
+                break;
+            }
+            inner.push(self.logical_and());
+        }
+
+        if inner.len() == 1 {
+            inner.pop().unwrap()
+        } else {
+            Expression::Or(inner)
+        }
+    }
+
+    fn logical_and(&mut self) -> Expression {
+        let mut inner = vec![self.basic_expression()];
+        loop {
+            self.skip_whitespace();
+            if !self.eat("&&") {
+                break;
+            }
+            inner.push(self.basic_expression());
+        }
+
+        if inner.len() == 1 {
+            inner.pop().unwrap()
+        } else {
+            Expression::And(inner)
+        }
+    }
+
+    fn basic_expression(&mut self) -> Expression {
+        self.skip_whitespace();
+
+        if self.eat("!") {
+            return Expression::Not(Box::new(self.basic_expression()));
+        }
+
+        if self.eat("(") {
+            let inner = self.logical_or();
+            self.skip_whitespace();
+            self.expect(")");
+            return inner;
+        }
+
+        let first = self.comparable();
+        self.skip_whitespace();
+
+        let comparison = if self.eat("==") {
+            Comparison::Eq
+        } else if self.eat("!=") {
+            Comparison::NotEq
+        } else if self.eat("<=") {
+            Comparison::Lte
+        } else if self.eat(">=") {
+            Comparison::Gte
+        } else if self.eat("<") {
+            Comparison::Lt
+        } else if self.eat(">") {
+            Comparison::Gt
+        } else {
+            // No comparison, so this has to be an existence test or a test function
+            return match first {
+                Comparable::Query(query) => Expression::Exists(query),
+                Comparable::Function(name, args) if name == "match" || name == "search" => {
+                    Expression::Test(Comparable::Function(name, args))
+                }
+                _ => self.fail("expected a comparison"),
+            };
+        };
+
+        self.skip_whitespace();
+        Expression::Compare(first, comparison, self.comparable())
+    }
+
+    fn comparable(&mut self) -> Comparable {
+        match self.peek() {
+            Some('$') => Comparable::Query(self.root_query()),
+            Some('@') => {
+                self.pos += 1;
+                Comparable::Query(Query {
+                    relative: true,
+                    segments: self.segments(),
+                })
+            }
+            Some('\'' | '"') => Comparable::Literal(JsonPP::String(self.string_literal())),
+            Some(ch) if ch == '-' || ch.is_ascii_digit() => Comparable::Literal(self.number()),
+            _ if self.eat("true") => Comparable::Literal(JsonPP::Bool(true)),
+            _ if self.eat("false") => Comparable::Literal(JsonPP::Bool(false)),
+            _ if self.eat("null") => Comparable::Literal(JsonPP::Null),
+            _ => {
+                let start = self.pos;
+                while self
+                    .peek()
+                    .is_some_and(|ch| ch == '_' || ch.is_ascii_lowercase() || ch.is_ascii_digit())
+                {
+                    self.pos += 1;
+                }
+                let name: String = self.chars[start..self.pos].iter().collect();
+                if name.is_empty() {
+                    self.fail("expected a value");
+                }
+
+                self.expect("(");
+                let mut args = vec![];
+                loop {
+                    self.skip_whitespace();
+                    args.push(self.comparable());
+                    self.skip_whitespace();
+                    if self.eat(")") {
+                        return Comparable::Function(name, args);
+                    }
+                    self.expect(",");
+                }
+            }
+        }
+    }
+
+    fn number(&mut self) -> JsonPP {
+        let start = self.pos;
+        while self
+            .peek()
+            .is_some_and(|ch| ch.is_ascii_digit() || "+-.eE".contains(ch))
+        {
+            self.pos += 1;
+        }
+
+        let literal: String = self.chars[start..self.pos].iter().collect();
+        if let Ok(integer) = literal.parse() {
+            return JsonPP::Int(integer);
+        }
+        if literal
+            .trim_start_matches('-')
+            .chars()
+            .all(|ch| ch.is_ascii_digit())
+        {
+            if let Ok(big) = literal.parse() {
+                return JsonPP::BigInt(big);
+            }
+        }
+
+        JsonPP::Float(
+            literal
+                .parse()
+                .unwrap_or_else(|_| self.fail(&format!("invalid number '{}'", literal))),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_bytes;
+
+    fn run(path: &str, document: &str) -> Vec<JsonPP> {
+        let root = parse_bytes(document.into());
+        query(path, &root).into_iter().cloned().collect()
+    }
+
+    #[test]
+    fn selectors() {
+        let document = r#"{"a": {"b": [1, 2, 3, 4]}, "c d": true}"#;
+
+        assert_eq!(run("$.a.b[0]", document), vec![JsonPP::Int(1)]);
+        assert_eq!(run("$['a'][\"b\"][-1]", document), vec![JsonPP::Int(4)]);
+        assert_eq!(
+            run("$.a.b[1:3]", document),
+            vec![JsonPP::Int(2), JsonPP::Int(3)]
+        );
+        assert_eq!(
+            run("$.a.b[::-2]", document),
+            vec![JsonPP::Int(4), JsonPP::Int(2)]
+        );
+        assert_eq!(
+            run("$.a.b[0, 3]", document),
+            vec![JsonPP::Int(1), JsonPP::Int(4)]
+        );
+        assert_eq!(run("$['c d']", document), vec![JsonPP::Bool(true)]);
+        assert_eq!(run("$.missing", document), vec![]);
+        assert_eq!(run("$..b[*]", document).len(), 4);
+    }
+
+    #[test]
+    fn filters() {
+        let document = r#"[
+            {"name": "a", "size": 1, "enabled": true},
+            {"name": "b", "size": 5},
+            {"name": "c", "size": 10, "enabled": false},
+        ]"#;
+
+        let names = |path| {
+            run(path, document)
+                .into_iter()
+                .map(|value| {
+                    let JsonPP::String(name) = value else {
+                        panic!("Non-string name");
+                    };
+                    name
+                })
+                .collect::<Vec<String>>()
+        };
+
+        assert_eq!(names("$[?@.enabled].name"), vec!["a", "c"]);
+        assert_eq!(names("$[?(!@.enabled)].name"), vec!["b"]);
+        assert_eq!(names("$[?@.size > 1 && @.size < 10].name"), vec!["b"]);
+        assert_eq!(
+            names("$[?@.size == 1 || @.name == 'c'].name"),
+            vec!["a", "c"]
+        );
+        assert_eq!(names("$[?@.enabled == false].name"), vec!["c"]);
+        assert_eq!(
+            names("$[?length(@.name) == 1 && @.size >= 5.0].name"),
+            vec!["b", "c"]
+        );
+        assert_eq!(names("$[?@.size == $[0].size].name"), vec!["a"]);
+    }
+
+    #[test]
+    fn regex_functions() {
+        // A literal carriage return, which a dot shouldn't match
+        let document = "[\"abc\", \"xabcx\", \"a\rc\", \"a.c\", 1]";
+
+        assert_eq!(
+            run("$[?match(@, 'a.c')]", document),
+            vec![JsonPP::String("abc".into()), JsonPP::String("a.c".into())]
+        );
+        assert_eq!(
+            run("$[?search(@, 'b')]", document),
+            vec![JsonPP::String("abc".into()), JsonPP::String("xabcx".into())]
+        );
+        assert_eq!(
+            run("$[?match(@, 'a\\\\.c')]", document),
+            vec![JsonPP::String("a.c".into())]
+        );
+        assert_eq!(run("$[?!search(@, '[')]", document).len(), 5);
+        assert_eq!(run("$[?search(@, '[.]')]", document).len(), 1);
+    }
+
+    #[test]
+    #[should_panic]
+    fn invalid_query() {
+        run("$.a[", "{}");
+    }
+}
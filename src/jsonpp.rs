@@ -1,4 +1,6 @@
-use std::collections::HashMap;
+use std::{cmp::Ordering, collections::HashMap};
+
+use bigdecimal::{num_bigint::BigInt, BigDecimal, ToPrimitive, Zero};
 
 use crate::{builtins, evaluation, paths::PathChunk};
 
@@ -10,6 +12,8 @@ pub(crate) enum JsonPP {
     String(String),
     Int(i64),
     Float(f64),
+    BigInt(BigInt),      // Integers that don't fit in an i64
+    Decimal(BigDecimal), // Exact decimals, created with (decimal)
     Array(Vec<JsonPP>),
     Object(HashMap<String, JsonPP>),
     Identifier(String),
@@ -24,6 +28,8 @@ impl JsonPP {
             JsonPP::String(val) => !val.is_empty(),
             JsonPP::Int(val) => *val != 0,
             JsonPP::Float(val) => *val != 0.0,
+            JsonPP::BigInt(val) => !val.is_zero(),
+            JsonPP::Decimal(val) => !val.is_zero(),
             JsonPP::Array(vec) => !vec.is_empty(),
             JsonPP::Object(hash_map) => !hash_map.is_empty(),
             other => panic!("Cannot evaluate truthiness of '{:?}'", other),
@@ -41,6 +47,18 @@ impl TryInto<Option<serde_json::Value>> for JsonPP {
             JsonPP::String(val) => serde_json::Value::String(val),
             JsonPP::Int(val) => serde_json::Value::from(val),
             JsonPP::Float(val) => serde_json::Value::from(val),
+            // Without arbitrary_precision serde_json can't hold these exactly, but
+            // to_json_string can
+            #[cfg(feature = "arbitrary_precision")]
+            JsonPP::BigInt(val) => serde_json::Value::Number(val.to_string().parse().unwrap()),
+            #[cfg(feature = "arbitrary_precision")]
+            JsonPP::Decimal(val) => {
+                serde_json::Value::Number(val.to_plain_string().parse().unwrap())
+            }
+            #[cfg(not(feature = "arbitrary_precision"))]
+            JsonPP::BigInt(val) => serde_json::Value::from(val.to_f64().unwrap()),
+            #[cfg(not(feature = "arbitrary_precision"))]
+            JsonPP::Decimal(val) => serde_json::Value::from(val.to_f64().unwrap()),
             JsonPP::Array(vec) => serde_json::Value::Array(
                 vec.into_iter()
                     .filter_map(|elem| {
@@ -73,12 +91,104 @@ impl TryInto<Option<serde_json::Value>> for JsonPP {
 }
 
 impl TryInto<serde_json::Value> for JsonPP {
-    type Error = ();
+    type Error = JsonPP;
 
     fn try_into(self) -> Result<serde_json::Value, Self::Error> {
+        // Only gets stripped inside arrays and objects, on its own there's nothing to convert to
+        if self.is_stripped() {
+            return Err(self);
+        }
+
         TryInto::<Option<serde_json::Value>>::try_into(self)
-            .map(|inner| inner.unwrap())
-            .map_err(|_| ())
+            .map(|inner| inner.unwrap_or(serde_json::Value::Null))
+    }
+}
+
+impl JsonPP {
+    pub fn to_json_string(&self, pretty: bool) -> Result<String, JsonPP> {
+        // Same layout as serde_json, keys sorted, but big numbers and decimals stay exact
+        if self.is_stripped() {
+            return Err(self.clone());
+        }
+
+        let mut out = String::new();
+        self.write_json(&mut out, pretty.then_some(0))?;
+        Ok(out)
+    }
+
+    fn write_json(&self, out: &mut String, depth: Option<usize>) -> Result<(), JsonPP> {
+        // Depth is None for compact output
+        let (open, separator, close) = match depth {
+            Some(depth) => (
+                format!("\n{}", "  ".repeat(depth + 1)),
+                format!(",\n{}", "  ".repeat(depth + 1)),
+                format!("\n{}", "  ".repeat(depth)),
+            ),
+            None => ("".to_owned(), ",".to_owned(), "".to_owned()),
+        };
+        let inner_depth = depth.map(|depth| depth + 1);
+
+        match self {
+            JsonPP::Null => out.push_str("null"),
+            JsonPP::Bool(val) => out.push_str(&val.to_string()),
+            JsonPP::String(val) => out.push_str(&serde_json::Value::from(val.as_str()).to_string()),
+            JsonPP::Int(val) => out.push_str(&val.to_string()),
+            JsonPP::Float(val) => out.push_str(&serde_json::Value::from(*val).to_string()),
+            JsonPP::BigInt(val) => out.push_str(&val.to_string()),
+            JsonPP::Decimal(val) => out.push_str(&val.to_plain_string()),
+            JsonPP::Array(vec) => {
+                let elems: Vec<&JsonPP> = vec.iter().filter(|elem| !elem.is_stripped()).collect();
+                if elems.is_empty() {
+                    out.push_str("[]");
+                    return Ok(());
+                }
+
+                out.push('[');
+                for (index, elem) in elems.into_iter().enumerate() {
+                    out.push_str(if index == 0 { &open } else { &separator });
+                    elem.write_json(out, inner_depth)?;
+                }
+                out.push_str(&close);
+                out.push(']');
+            }
+            JsonPP::Object(hash_map) => {
+                let mut entries: Vec<(&String, &JsonPP)> = hash_map
+                    .iter()
+                    .filter(|(_, elem)| !elem.is_stripped())
+                    .collect();
+                if entries.is_empty() {
+                    out.push_str("{}");
+                    return Ok(());
+                }
+
+                entries.sort_by_key(|(key, _)| *key);
+                out.push('{');
+                for (index, (key, elem)) in entries.into_iter().enumerate() {
+                    out.push_str(if index == 0 { &open } else { &separator });
+                    out.push_str(&serde_json::Value::from(key.as_str()).to_string());
+                    out.push_str(if depth.is_some() { ": " } else { ":" });
+                    elem.write_json(out, inner_depth)?;
+                }
+                out.push_str(&close);
+                out.push('}');
+            }
+            other => return Err(other.clone()),
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn is_stripped(&self) -> bool {
+        matches!(self, JsonPP::Undefined | JsonPP::Definition(_))
+    }
+}
+
+impl From<BigInt> for JsonPP {
+    fn from(value: BigInt) -> Self {
+        match value.to_i64() {
+            Some(int) => JsonPP::Int(int),
+            None => JsonPP::BigInt(value),
+        }
     }
 }
 
@@ -93,6 +203,8 @@ pub(crate) struct Dynamic {
     pub args: Vec<JsonPP>,
     pub path: Vec<PathChunk>,
     pub dependencies: Vec<Vec<PathChunk>>,
+    // Set on the call a callback function expands into, which has the results as an extra argument
+    pub results: bool,
 }
 
 impl Dynamic {
@@ -103,6 +215,10 @@ impl Dynamic {
     pub fn is_ref(&self) -> bool {
         self.args[0] == JsonPP::Identifier("ref".to_owned())
     }
+
+    pub fn is_ref_or(&self) -> bool {
+        self.args[0] == JsonPP::Identifier("ref_or".to_owned())
+    }
 }
 
 impl Dynamic {
@@ -112,37 +228,158 @@ impl Dynamic {
         let (cmd, args) = self.args.split_at(1);
 
         match cmd[0].to_owned() {
+            JsonPP::Identifier(fun) if self.results => match fun.as_str() {
+                "sort_by" => builtins::sort_by_impl(args.to_vec()),
+                "find" => builtins::find_impl(args.to_vec()),
+                "group_by" => builtins::group_by_impl(args.to_vec()),
+                "partition" => builtins::partition_impl(args.to_vec()),
+                "count_by" => builtins::count_by_impl(args.to_vec()),
+                "index_by" => builtins::index_by_impl(args.to_vec()),
+                "map_keys" => builtins::map_keys_impl(args.to_vec()),
+                "map_entries" => builtins::map_entries_impl(args.to_vec()),
+                other => panic!("'{}' doesn't call a function for each element", other),
+            },
             JsonPP::Identifier(fun) => match fun.as_str() {
                 "sum" => builtins::sum_impl(args.to_vec()),
                 "sub" => builtins::sub_impl(args.to_vec()),
                 "mul" => builtins::mul_impl(args.to_vec()),
                 "div" => builtins::div_impl(args.to_vec()),
+                "idiv" => builtins::idiv_impl(args.to_vec()),
+                "fdiv" => builtins::fdiv_impl(args.to_vec()),
                 "mod" => builtins::mod_impl(args.to_vec()),
                 "pow" => builtins::pow_impl(args.to_vec()),
                 "log" => builtins::log_impl(args.to_vec()),
                 "len" => builtins::len_impl(args.to_vec()),
+                "byte_len" => builtins::byte_len_impl(args.to_vec()),
+                "grapheme_len" => builtins::grapheme_len_impl(args.to_vec()),
                 "ref" => builtins::ref_impl(args.to_vec(), path, root),
+                "ref_or" => builtins::ref_or_impl(args.to_vec(), path, root),
+                "get" => builtins::get_impl(args.to_vec()),
+                "get_in" => builtins::get_in_impl(args.to_vec()),
+                "has" => builtins::has_impl(args.to_vec()),
+                "query" => builtins::query_impl(args.to_vec()),
                 "min" => builtins::min_impl(args.to_vec()),
                 "max" => builtins::max_impl(args.to_vec()),
+                "avg" => builtins::avg_impl(args.to_vec()),
+                "median" => builtins::median_impl(args.to_vec()),
+                "percentile" => builtins::percentile_impl(args.to_vec()),
+                "variance" => builtins::variance_impl(args.to_vec()),
+                "stddev" => builtins::stddev_impl(args.to_vec()),
+                "abs" => builtins::abs_impl(args.to_vec()),
+                "sign" => builtins::sign_impl(args.to_vec()),
+                "round" => builtins::round_impl(args.to_vec()),
+                "floor" => builtins::floor_impl(args.to_vec()),
+                "ceil" => builtins::ceil_impl(args.to_vec()),
+                "trunc" => builtins::trunc_impl(args.to_vec()),
+                "clamp" => builtins::clamp_impl(args.to_vec()),
+                "sqrt" => builtins::float_fn_impl("sqrt", args.to_vec(), f64::sqrt),
+                "exp" => builtins::float_fn_impl("exp", args.to_vec(), f64::exp),
+                "ln" => builtins::float_fn_impl("ln", args.to_vec(), f64::ln),
+                "sin" => builtins::float_fn_impl("sin", args.to_vec(), f64::sin),
+                "cos" => builtins::float_fn_impl("cos", args.to_vec(), f64::cos),
+                "tan" => builtins::float_fn_impl("tan", args.to_vec(), f64::tan),
+                "asin" => builtins::float_fn_impl("asin", args.to_vec(), f64::asin),
+                "acos" => builtins::float_fn_impl("acos", args.to_vec(), f64::acos),
+                "atan" => builtins::float_fn_impl("atan", args.to_vec(), f64::atan),
+                "atan2" => builtins::atan2_impl(args.to_vec()),
+                "pi" => builtins::constant_impl("pi", args.to_vec(), std::f64::consts::PI),
+                "e" => builtins::constant_impl("e", args.to_vec(), std::f64::consts::E),
+                "band" => builtins::band_impl(args.to_vec()),
+                "bor" => builtins::bor_impl(args.to_vec()),
+                "bxor" => builtins::bxor_impl(args.to_vec()),
+                "bnot" => builtins::bnot_impl(args.to_vec()),
+                "shl" => builtins::shl_impl(args.to_vec()),
+                "shr" => builtins::shr_impl(args.to_vec()),
+                "parse_int" => builtins::parse_int_impl(args.to_vec()),
+                "to_radix" => builtins::to_radix_impl(args.to_vec()),
                 "not" => builtins::not_impl(args.to_vec()),
                 "eq" => builtins::eq_impl(args.to_vec()),
-                "gt" => builtins::num_cmp(args.to_vec(), |a, b| a > b, |a, b| a > b),
-                "lt" => builtins::num_cmp(args.to_vec(), |a, b| a < b, |a, b| a < b),
-                "gte" => builtins::num_cmp(args.to_vec(), |a, b| a >= b, |a, b| a >= b),
-                "lte" => builtins::num_cmp(args.to_vec(), |a, b| a <= b, |a, b| a <= b),
+                "gt" => builtins::num_cmp(args.to_vec(), Ordering::is_gt),
+                "lt" => builtins::num_cmp(args.to_vec(), Ordering::is_lt),
+                "gte" => builtins::num_cmp(args.to_vec(), Ordering::is_ge),
+                "lte" => builtins::num_cmp(args.to_vec(), Ordering::is_le),
                 "if" => builtins::if_impl(args.to_vec()),
                 "include" => builtins::include_impl(args.to_vec()),
                 "import" => builtins::import_impl(args.to_vec()),
                 "str" => builtins::str_impl(args.to_vec()),
                 "int" => builtins::int_impl(args.to_vec()),
                 "float" => builtins::float_impl(args.to_vec()),
+                "decimal" => builtins::decimal_impl(args.to_vec()),
                 "range" => builtins::range_impl(args.to_vec()),
                 "merge" => builtins::merge_impl(args.to_vec()),
+                "deep_merge" => builtins::deep_merge_impl(args.to_vec()),
+                "deep_merge_with" => builtins::deep_merge_with_impl(args.to_vec()),
+                "upper" => builtins::upper_impl(args.to_vec()),
+                "lower" => builtins::lower_impl(args.to_vec()),
+                "trim" => builtins::trim_impl(args.to_vec()),
+                "trim_start" => builtins::trim_start_impl(args.to_vec()),
+                "trim_end" => builtins::trim_end_impl(args.to_vec()),
+                "split" => builtins::split_impl(args.to_vec()),
+                "join" => builtins::join_impl(args.to_vec()),
+                "replace" => builtins::replace_impl(args.to_vec()),
+                "contains" => builtins::contains_impl(args.to_vec()),
+                "starts_with" => builtins::starts_with_impl(args.to_vec()),
+                "ends_with" => builtins::ends_with_impl(args.to_vec()),
+                "repeat" => builtins::repeat_impl(args.to_vec()),
+                "pad_start" => builtins::pad_start_impl(args.to_vec()),
+                "pad_end" => builtins::pad_end_impl(args.to_vec()),
+                "lines" => builtins::lines_impl(args.to_vec()),
+                "substr" => builtins::substr_impl(args.to_vec()),
+                "char_at" => builtins::char_at_impl(args.to_vec()),
+                "chars" => builtins::chars_impl(args.to_vec()),
+                "format" => builtins::format_impl(args.to_vec()),
+                "re_match" => builtins::re_match_impl(args.to_vec()),
+                "re_find" => builtins::re_find_impl(args.to_vec()),
+                "re_replace" => builtins::re_replace_impl(args.to_vec()),
+                "re_split" => builtins::re_split_impl(args.to_vec()),
+                "base64_encode" => builtins::base64_encode_impl(args.to_vec()),
+                "base64_decode" => builtins::base64_decode_impl(args.to_vec()),
+                "hex_encode" => builtins::hex_encode_impl(args.to_vec()),
+                "hex_decode" => builtins::hex_decode_impl(args.to_vec()),
+                "url_encode" => builtins::url_encode_impl(args.to_vec()),
+                "url_decode" => builtins::url_decode_impl(args.to_vec()),
+                "to_json" => builtins::to_json_impl(args.to_vec()),
+                "from_json" => builtins::from_json_impl(args.to_vec()),
+                "sha256" => builtins::sha256_impl(args.to_vec()),
+                "sha1" => builtins::sha1_impl(args.to_vec()),
+                "md5" => builtins::md5_impl(args.to_vec()),
+                "crc32" => builtins::crc32_impl(args.to_vec()),
+                "uuid_v5" => builtins::uuid_v5_impl(args.to_vec()),
+                "uuid_v4" => builtins::uuid_v4_impl(args.to_vec()),
+                "slug" => builtins::slug_impl(args.to_vec()),
+                "random" => builtins::random_impl(args.to_vec()),
+                "random_int" => builtins::random_int_impl(args.to_vec()),
+                "shuffle" => builtins::shuffle_impl(args.to_vec()),
+                "sample" => builtins::sample_impl(args.to_vec()),
                 "def" => builtins::def_impl(args.to_vec()),
                 "map" => builtins::map_impl(args.to_vec()),
                 "filter" => builtins::filter_impl(args.to_vec()),
                 "reduce" => builtins::reduce_impl(args.to_vec()),
+                "sort" => builtins::sort_impl(args.to_vec()),
+                "sort_by" => builtins::call_for_each("sort_by", args.to_vec()),
+                "find" => builtins::call_for_each("find", args.to_vec()),
+                "group_by" => builtins::call_for_each("group_by", args.to_vec()),
+                "partition" => builtins::call_for_each("partition", args.to_vec()),
+                "count_by" => builtins::call_for_each("count_by", args.to_vec()),
+                "index_by" => builtins::call_for_each("index_by", args.to_vec()),
+                "reverse" => builtins::reverse_impl(args.to_vec()),
+                "unique" => builtins::unique_impl(args.to_vec()),
+                "flatten" => builtins::flatten_impl(args.to_vec()),
+                "zip" => builtins::zip_impl(args.to_vec()),
+                "enumerate" => builtins::enumerate_impl(args.to_vec()),
+                "slice" => builtins::slice_impl(args.to_vec()),
+                "take" => builtins::take_impl(args.to_vec()),
+                "drop" => builtins::drop_impl(args.to_vec()),
+                "index_of" => builtins::index_of_impl(args.to_vec()),
                 "keys" => builtins::keys_impl(args.to_vec()),
+                "entries" => builtins::entries_impl(args.to_vec()),
+                "from_entries" => builtins::from_entries_impl(args.to_vec()),
+                "pick" => builtins::pick_impl(args.to_vec()),
+                "omit" => builtins::omit_impl(args.to_vec()),
+                "rename_keys" => builtins::rename_keys_impl(args.to_vec()),
+                "map_keys" => builtins::call_for_each_entry("map_keys", args.to_vec(), false),
+                "map_entries" => builtins::call_for_each_entry("map_entries", args.to_vec(), true),
+                "invert" => builtins::invert_impl(args.to_vec()),
                 "values" => builtins::values_impl(args.to_vec()),
                 "shell" => builtins::shell_impl(args.to_vec()),
                 other => panic!("Unrecognized function '{}'", other),
@@ -10,6 +10,7 @@ use serde_json::Value;
 mod ast_builder;
 mod builtins;
 mod evaluation;
+mod jsonpath;
 mod jsonpp;
 mod paths;
 mod tokenizing;
@@ -31,14 +32,29 @@ pub fn evaluate_bytes(bytes: Vec<u8>) -> Value {
     evaluated
 }
 
+/// Like evaluate_bytes, but as pretty printed json where big integers and decimals keep every digit
+pub fn evaluate_to_string(bytes: Vec<u8>) -> String {
+    let ast = parse_bytes(bytes);
+    info!("Evaluating input");
+    let evaluated = evaluation::evaluate_to_string(ast);
+    info!("Input evaluated");
+    evaluated
+}
+
 #[derive(Debug, clap::Parser)]
 #[command(version, about, long_about = None)]
 pub struct Args {
     /// Name of input file, or - for stdin
     input: String,
+
+    /// Turn integers that overflow into floats instead of exiting
+    #[arg(long)]
+    overflow_to_float: bool,
 }
 impl Args {
     pub fn execute(self) {
+        builtins::set_overflow_to_float(self.overflow_to_float);
+
         let mut input_buf = vec![];
         let read_result = if self.input == "-" {
             stdin().read_to_end(&mut input_buf).unwrap()
@@ -50,9 +66,7 @@ impl Args {
 
         debug!("Read in {read_result} bytes");
 
-        let output = evaluate_bytes(input_buf);
-
-        println!("{}", serde_json::to_string_pretty(&output).unwrap());
+        println!("{}", evaluate_to_string(input_buf));
     }
 }
 
@@ -165,6 +179,238 @@ mod tests {
         evaluate_to_equivalent("div");
     }
 
+    #[test]
+    fn math() {
+        evaluate_to_equivalent("math");
+    }
+
+    #[test]
+    #[should_panic(expected = "'sqrt' is not defined for -1")]
+    fn math_domain_error() {
+        evaluate_bytes("(sqrt -1)".into());
+    }
+
+    #[test]
+    fn collections() {
+        evaluate_to_equivalent("collections");
+    }
+
+    #[test]
+    #[should_panic(expected = "'sort' can only order numbers or strings")]
+    fn sort_mixed_types() {
+        evaluate_bytes("(sort [1 \"a\"])".into());
+    }
+
+    #[test]
+    #[should_panic(expected = "Can't convert Undefined to json")]
+    fn to_json_undefined() {
+        evaluate_bytes("(to_json undefined)".into());
+    }
+
+    #[test]
+    #[should_panic(expected = "Can't convert Undefined to json")]
+    fn undefined_document() {
+        evaluate_bytes("undefined".into());
+    }
+
+    #[test]
+    #[should_panic(expected = "'sort_by' takes a function and an array, found 3 arguments")]
+    fn sort_by_with_extra_argument() {
+        evaluate_bytes(r#"(sort_by (def x x) [3 1 2] [0 0 0])"#.into());
+    }
+
+    #[test]
+    #[should_panic(expected = "'map_keys' takes a function and an object, found 3 arguments")]
+    fn map_keys_with_extra_argument() {
+        evaluate_bytes(r#"(map_keys (def k k) {"a": 1} ["b"])"#.into());
+    }
+
+    #[test]
+    fn grouping() {
+        evaluate_to_equivalent("grouping");
+    }
+
+    #[test]
+    fn object_transformations() {
+        evaluate_to_equivalent("objects");
+    }
+
+    #[test]
+    fn deep_merge() {
+        evaluate_to_equivalent("deep_merge");
+    }
+
+    #[test]
+    #[should_panic(expected = "Can't merge a number with a string at 'server.port'")]
+    fn deep_merge_conflict() {
+        evaluate_bytes(
+            r#"(deep_merge_with {"types": "error"} {"server": {"port": 80}} {"server": {"port": "80"}})"#
+                .into(),
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "'deep_merge_with' option 'key' only works with 'arrays': 'merge'")]
+    fn deep_merge_key_without_merge() {
+        evaluate_bytes(r#"(deep_merge_with {"key": "name"} [] [])"#.into());
+    }
+
+    #[test]
+    fn bitwise() {
+        evaluate_to_equivalent("bitwise");
+    }
+
+    #[test]
+    #[should_panic(expected = "Integer overflow with operands 1 and 64")]
+    fn shift_overflow() {
+        evaluate_bytes("(shl 1 64)".into());
+    }
+
+    #[test]
+    #[should_panic(expected = "'parse_int' can't parse '12a' in base 10")]
+    fn invalid_int() {
+        evaluate_bytes("(parse_int \"12a\")".into());
+    }
+
+    #[test]
+    #[should_panic(expected = "'parse_int' can't parse '€1' in base 10")]
+    fn non_ascii_int() {
+        evaluate_bytes("(parse_int \"€1\")".into());
+    }
+
+    #[test]
+    fn division_semantics() {
+        evaluate_to_equivalent("division");
+    }
+
+    #[test]
+    #[should_panic(expected = "Division by zero")]
+    fn integer_division_by_zero() {
+        evaluate_bytes("(idiv 1 0)".into());
+    }
+
+    #[test]
+    fn statistics() {
+        evaluate_to_equivalent("statistics");
+    }
+
+    #[test]
+    fn dynamics_inside_arguments() {
+        // The outer call has to wait for the ones inside its array and object arguments,
+        // many of them so that a lucky resolution order can't hide it
+        let calls: Vec<String> = (0..20)
+            .map(|i| format!(r#"(sum (sum [(sum {i} (ref "one"))]) (len {{"a": (ref "one")}}))"#))
+            .collect();
+        let eval = evaluate_bytes(
+            format!(r#"{{"one": (sum 0 1), "calls": [{}]}}"#, calls.join(" ")).into(),
+        );
+
+        let expected: Vec<i64> = (0..20).map(|i| i + 2).collect();
+        assert_eq!(eval["calls"], serde_json::json!(expected));
+    }
+
+    #[test]
+    #[should_panic(expected = "'median' needs at least one number")]
+    fn statistics_of_nothing() {
+        evaluate_bytes("(median [])".into());
+    }
+
+    #[test]
+    fn random() {
+        evaluate_to_equivalent("random");
+    }
+
+    #[test]
+    fn random_is_independent_of_order() {
+        let alone = evaluate_bytes("(shuffle 42 (range 0 20))".into());
+        let surrounded = evaluate_bytes(
+            "[(random 42) (shuffle 42 (range 0 20)) (sample 42 (range 0 20) 3)]".into(),
+        );
+
+        assert_eq!(surrounded[1], alone);
+        assert_eq!(
+            surrounded[2].as_array().unwrap(),
+            &alone.as_array().unwrap()[0..3]
+        );
+    }
+
+    #[test]
+    fn big_numbers() {
+        // Compared as text, the serde_json values have lost the extra digits
+        let input = read_file("parseables/evaluation_inputs/big_numbers.jsonpp");
+        let output = read_file("parseables/evaluation_outputs/big_numbers.json");
+        assert_eq!(evaluate_to_string(input), evaluate_to_string(output));
+    }
+
+    #[test]
+    fn exact_output() {
+        assert_eq!(
+            evaluate_to_string(
+                "[123456789012345678901234567890 (sum (decimal \"0.1\") (decimal \"0.2\")) 1.5 {}]"
+                    .into()
+            ),
+            "[\n  123456789012345678901234567890,\n  0.3,\n  1.5,\n  {}\n]"
+        );
+
+        // Everything else looks like serde_json output
+        let contents = read_file("parseables/serde_comparison/wikipedia.json");
+        assert_eq!(
+            evaluate_to_string(contents.clone()),
+            serde_json::to_string_pretty(&evaluate_bytes(contents.clone())).unwrap()
+        );
+        let compact = evaluate_to_string(
+            format!("(to_json {})", String::from_utf8(contents.clone()).unwrap()).into(),
+        );
+        assert_eq!(
+            evaluate_bytes(compact.into()),
+            serde_json::json!(serde_json::to_string(&evaluate_bytes(contents)).unwrap())
+        );
+    }
+
+    #[test]
+    fn exact_builtins() {
+        let huge = format!("1{}", "0".repeat(400));
+        assert_eq!(
+            evaluate_bytes(format!("(to_json [{} (decimal \"0.10\")])", huge).into()),
+            serde_json::json!(format!("[{},0.10]", huge))
+        );
+        assert_ne!(
+            evaluate_bytes("(sha256 123456789012345678901234567890)".into()),
+            evaluate_bytes("(sha256 123456789012345678901234567891)".into())
+        );
+        assert_eq!(
+            evaluate_to_string(
+                r#"(from_json "{\"big\": 123456789012345678901234567890, \"s\": \"a\\u00e9\\r\"}")"#
+                    .into()
+            ),
+            "{\n  \"big\": 123456789012345678901234567890,\n  \"s\": \"aé\\r\"\n}"
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Integer overflow with operands 9223372036854775807 and 1")]
+    fn integer_overflow() {
+        evaluate_bytes("(sum 9223372036854775807 1)".into());
+    }
+
+    #[test]
+    #[should_panic(expected = "Integer overflow with operands 0 and -1")]
+    fn zero_to_negative_power() {
+        evaluate_bytes("(pow 0 -1)".into());
+    }
+
+    #[test]
+    fn integer_overflow_to_float() {
+        builtins::set_overflow_to_float(true);
+        let eval = evaluate_bytes("[(mul 4294967296 4294967296) (pow 2 64) (sum 1 2)]".into());
+        builtins::set_overflow_to_float(false);
+
+        assert_eq!(
+            eval,
+            serde_json::json!([18446744073709551616.0, 18446744073709551616.0, 3])
+        );
+    }
+
     #[test]
     fn pows() {
         evaluate_to_equivalent("pow");
@@ -180,6 +426,11 @@ mod tests {
         evaluate_to_equivalent("len");
     }
 
+    #[test]
+    fn substrings() {
+        evaluate_to_equivalent("substr");
+    }
+
     #[test]
     fn mins() {
         evaluate_to_equivalent("min");
@@ -240,6 +491,91 @@ mod tests {
         evaluate_to_equivalent("float");
     }
 
+    #[test]
+    fn string_functions() {
+        evaluate_to_equivalent("strings");
+    }
+
+    #[test]
+    fn formatting() {
+        evaluate_to_equivalent("format");
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "'join' expects a string as element 1 of the array in argument 0, found Int(2)"
+    )]
+    fn join_non_string() {
+        evaluate_bytes("(join [\"a\" 2] \",\")".into());
+    }
+
+    #[test]
+    fn uninterpolated_strings() {
+        assert_eq!(
+            evaluate_bytes(
+                r#"{"a": 1, "escaped": "$${ref:a}", "env": "${HOME}/x", "mixed": "${ref:a} $${ref:a} ${HOME}"}"#
+                    .into()
+            ),
+            serde_json::json!({
+                "a": 1,
+                "escaped": "${ref:a}",
+                "env": "${HOME}/x",
+                "mixed": "1 ${ref:a} ${HOME}"
+            })
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Unmatched '{' in format string '{0'")]
+    fn unterminated_placeholder() {
+        evaluate_bytes("(format \"{0\" 1)".into());
+    }
+
+    #[test]
+    fn encodings() {
+        evaluate_to_equivalent("encoding");
+    }
+
+    #[test]
+    fn hashes() {
+        evaluate_to_equivalent("hash");
+    }
+
+    #[test]
+    fn identifiers() {
+        evaluate_to_equivalent("identifiers");
+    }
+
+    #[test]
+    #[should_panic(expected = "'slug' found no letters or digits in '!!!'")]
+    fn empty_slug() {
+        evaluate_bytes("(slug \"!!!\")".into());
+    }
+
+    #[test]
+    fn regexes() {
+        evaluate_to_equivalent("regex");
+    }
+
+    #[test]
+    fn more_regexes_than_cached() {
+        // More patterns than the cache keeps, so some are compiled again after being evicted
+        let calls: Vec<String> = (0..40)
+            .chain(0..40)
+            .map(|i| format!("(re_match \"^x{}$\" \"x{}\")", i, i % 20))
+            .collect();
+        let matches = evaluate_bytes(format!("[{}]", calls.join(" ")).into());
+
+        let expected: Vec<bool> = (0..80).map(|i| i % 40 < 20).collect();
+        assert_eq!(matches, serde_json::json!(expected));
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid regex")]
+    fn invalid_regex() {
+        evaluate_bytes("(re_match \"(unclosed\" \"foo\")".into());
+    }
+
     #[test]
     fn merges() {
         evaluate_to_equivalent("merge");
@@ -260,11 +596,75 @@ mod tests {
         evaluate_to_equivalent("ref_dynamic");
     }
 
+    #[test]
+    fn reference_quoted_keys() {
+        evaluate_to_equivalent("ref_quoted");
+    }
+
+    #[test]
+    fn reference_json_pointer() {
+        evaluate_to_equivalent("json_pointer");
+    }
+
+    #[test]
+    fn reference_wildcards() {
+        evaluate_to_equivalent("ref_wildcard");
+    }
+
+    #[test]
+    fn wildcard_refs_to_dynamics() {
+        // Evaluation order depends on hashing, so the same document is evaluated many times
+        let document = r#"{
+            "services": {"web": {"port": 80, "name": "web"}, "db": {"port": 5432}},
+            "copy": (ref "services.*"),
+            "ports": (ref "**.port"),
+            "names": (ref "**.name"),
+            "name_count": (len (ref "**.name")),
+            "later": {"p": (ref "services.web.*")},
+            "all_p": (ref "**.p"),
+        }"#;
+
+        for _ in 0..20 {
+            let eval = evaluate_bytes(document.into());
+            assert_eq!(eval["ports"], serde_json::json!([5432, 80, 5432, 80]));
+            assert_eq!(eval["names"], serde_json::json!(["web", "web"]));
+            assert_eq!(eval["name_count"], serde_json::json!(2));
+            assert_eq!(eval["all_p"], serde_json::json!([["web", 80]]));
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "Reference cycle")]
+    fn wildcard_refs_matching_each_other() {
+        evaluate_bytes(r#"{"a": (ref "b.**"), "b": {"x": (ref "a.**")}}"#.into());
+    }
+
+    #[test]
+    fn reference_slices() {
+        evaluate_to_equivalent("ref_slice");
+    }
+
     #[test]
     fn reference_into_import() {
         evaluate_to_equivalent("ref_into_import");
     }
 
+    #[test]
+    fn safe_lookups() {
+        evaluate_to_equivalent("get");
+    }
+
+    #[test]
+    #[should_panic(expected = "Can't get Int(0) from Object")]
+    fn get_wrong_key_type() {
+        evaluate_bytes(r#"(get {"k": 1} 0)"#.into());
+    }
+
+    #[test]
+    fn jsonpath_query() {
+        evaluate_to_equivalent("query");
+    }
+
     #[test]
     fn import_and_include() {
         evaluate_to_equivalent("import");
@@ -3,7 +3,28 @@ pub(crate) enum PathChunk {
     Parent,
     Key(String),
     Index(usize),
+    IndexFromEnd(usize), // 1 is the last element
     Argument(usize),
+    Wildcard,      // Every element of an array or value of an object
+    IndexWildcard, // Every element of an array
+    Descendants,   // Any number of levels, including zero
+    Slice {
+        start: Option<i64>,
+        end: Option<i64>,
+        step: Option<i64>,
+    },
+}
+
+pub(crate) fn has_wildcards(path: &[PathChunk]) -> bool {
+    path.iter().any(|chunk| {
+        matches!(
+            chunk,
+            PathChunk::Wildcard
+                | PathChunk::IndexWildcard
+                | PathChunk::Descendants
+                | PathChunk::Slice { .. }
+        )
+    })
 }
 
 pub(crate) fn make_absolute(self_path: &[PathChunk], target_path: &[PathChunk]) -> Vec<PathChunk> {
@@ -26,15 +47,49 @@ pub(crate) fn make_absolute(self_path: &[PathChunk], target_path: &[PathChunk])
 }
 
 pub(crate) fn ref_chain(path: String) -> Vec<PathChunk> {
-    path.split(".")
-        .map(|chunk| {
-            if chunk.is_empty() {
-                return PathChunk::Parent;
+    if path.starts_with('/') {
+        return json_pointer_chain(&path);
+    }
+
+    if let Some((levels, pointer)) = relative_json_pointer(&path) {
+        // Zero levels up is the ref itself, same as a lone period
+        let mut chain = vec![PathChunk::Parent; levels + 1];
+        if pointer != "#" {
+            chain.extend(json_pointer_chain(pointer));
+        }
+        return chain;
+    }
+
+    split_chunks(&path)
+        .into_iter()
+        .map(|(chunk, quoted)| {
+            if quoted {
+                // Quoted chunks are always keys, no matter what they contain
+                return PathChunk::Key(chunk);
+            }
+
+            match chunk.as_str() {
+                "" => return PathChunk::Parent,
+                "*" => return PathChunk::Wildcard,
+                "[*]" => return PathChunk::IndexWildcard,
+                "**" => return PathChunk::Descendants,
+                _ => {}
             }
 
             if chunk.starts_with("[") && chunk.ends_with("]") {
                 let inner = &chunk[1..(chunk.len() - 1)];
-                return PathChunk::Index(inner.parse().unwrap());
+                if inner.contains(':') {
+                    return slice_chunk(inner);
+                }
+
+                let index: i64 = inner
+                    .parse()
+                    .unwrap_or_else(|_| panic!("Invalid array index: '{}'", chunk));
+                return if index < 0 {
+                    PathChunk::IndexFromEnd(index.unsigned_abs() as usize)
+                } else {
+                    PathChunk::Index(index as usize)
+                };
             }
 
             if chunk.starts_with("(") && chunk.ends_with(")") {
@@ -42,11 +97,213 @@ pub(crate) fn ref_chain(path: String) -> Vec<PathChunk> {
                 return PathChunk::Argument(inner.parse().unwrap());
             }
 
-            PathChunk::Key(chunk.to_owned())
+            PathChunk::Key(chunk)
         })
         .collect()
 }
 
+fn slice_chunk(inner: &str) -> PathChunk {
+    // Python style start:end:step, all parts optional
+    let parts: Vec<Option<i64>> = inner
+        .split(':')
+        .map(|part| {
+            if part.is_empty() {
+                None
+            } else {
+                Some(
+                    part.parse()
+                        .unwrap_or_else(|_| panic!("Invalid slice: '[{}]'", inner)),
+                )
+            }
+        })
+        .collect();
+
+    let [start, end, step] = match parts.as_slice() {
+        [start, end] => [*start, *end, None],
+        [start, end, step] => [*start, *end, *step],
+        _ => panic!("Invalid slice: '[{}]'", inner),
+    };
+
+    if step == Some(0) {
+        panic!("Slice step can't be zero: '[{}]'", inner);
+    }
+
+    PathChunk::Slice { start, end, step }
+}
+
+pub(crate) fn slice_indices(
+    len: usize,
+    start: Option<i64>,
+    end: Option<i64>,
+    step: Option<i64>,
+) -> Vec<usize> {
+    // Same semantics as Python, out of bounds gets clamped and negative counts from the end
+    let len = len as i64;
+    let step = step.unwrap_or(1);
+    let resolve = |bound: i64, lowest: i64, highest: i64| {
+        let absolute = if bound < 0 { bound + len } else { bound };
+        absolute.clamp(lowest, highest)
+    };
+
+    if step > 0 {
+        let start = start.map_or(0, |bound| resolve(bound, 0, len));
+        let end = end.map_or(len, |bound| resolve(bound, 0, len));
+        (start..end)
+            .step_by(step as usize)
+            .map(|index| index as usize)
+            .collect()
+    } else {
+        // Going backwards, -1 is before the first element
+        let start = start.map_or(len - 1, |bound| resolve(bound, -1, len - 1));
+        let end = end.map_or(-1, |bound| resolve(bound, -1, len - 1));
+        let mut indices = vec![];
+        let mut index = start;
+        while index > end {
+            indices.push(index as usize);
+            index += step;
+        }
+        indices
+    }
+}
+
+fn split_chunks(path: &str) -> Vec<(String, bool)> {
+    // Splits on periods, except for those inside single quotes
+    // Within quotes, a backslash escapes the next character
+    let mut chunks = vec![];
+    let mut stream = path.chars();
+
+    loop {
+        let mut chunk = String::new();
+        let mut quoted = false;
+        let mut ended = true;
+
+        while let Some(next_char) = stream.next() {
+            if next_char == '.' {
+                ended = false;
+                break;
+            }
+
+            if next_char == '\'' && chunk.is_empty() && !quoted {
+                quoted = true;
+                loop {
+                    match stream.next() {
+                        Some('\'') => break,
+                        Some('\\') => chunk.push(
+                            stream
+                                .next()
+                                .unwrap_or_else(|| panic!("Path ended mid escape: '{}'", path)),
+                        ),
+                        Some(ch) => chunk.push(ch),
+                        None => panic!("Unterminated quote in path: '{}'", path),
+                    }
+                }
+
+                match stream.next() {
+                    Some('.') => {
+                        ended = false;
+                        break;
+                    }
+                    None => break,
+                    Some(other) => panic!(
+                        "Expected a period after a quoted key, found '{}' in path: '{}'",
+                        other, path
+                    ),
+                }
+            }
+
+            chunk.push(next_char);
+        }
+
+        chunks.push((chunk, quoted));
+        if ended {
+            return chunks;
+        }
+    }
+}
+
+fn json_pointer_chain(pointer: &str) -> Vec<PathChunk> {
+    // RFC 6901, each token is a key or an array index, which is decided when fetching
+    pointer
+        .split('/')
+        .skip(1)
+        .map(|token| PathChunk::Key(token.replace("~1", "/").replace("~0", "~")))
+        .collect()
+}
+
+pub(crate) fn relative_json_pointer(path: &str) -> Option<(usize, &str)> {
+    // Relative JSON Pointer, a number of levels to go up, followed by a JSON Pointer or #
+    // A lone number is left alone, as that is a perfectly good object key
+    let digits = path.chars().take_while(char::is_ascii_digit).count();
+    let (levels, pointer) = path.split_at(digits);
+
+    if digits == 0 || (digits > 1 && levels.starts_with('0')) {
+        return None;
+    }
+
+    if pointer == "#" || pointer.starts_with('/') {
+        Some((levels.parse().ok()?, pointer))
+    } else {
+        None
+    }
+}
+
+pub(crate) fn array_index(key: &str) -> Option<usize> {
+    // Array indices as they appear in JSON Pointers, no leading zeroes
+    if key.is_empty() || !key.chars().all(|ch| ch.is_ascii_digit()) {
+        return None;
+    }
+
+    if key.len() > 1 && key.starts_with('0') {
+        return None;
+    }
+
+    key.parse().ok()
+}
+
+pub(crate) fn format_path(path: &[PathChunk]) -> String {
+    // Inverse of ref_chain
+    if path.is_empty() {
+        // There is no way to express the root itself, an empty string is a self reference
+        return "".to_owned();
+    }
+
+    path.iter()
+        .map(|chunk| match chunk {
+            PathChunk::Parent => "".to_owned(),
+            PathChunk::Index(index) => format!("[{}]", index),
+            PathChunk::IndexFromEnd(index) => format!("[-{}]", index),
+            PathChunk::Slice { start, end, step } => {
+                let part =
+                    |bound: &Option<i64>| bound.map(|val| val.to_string()).unwrap_or_default();
+                match step {
+                    Some(step) => format!("[{}:{}:{}]", part(start), part(end), step),
+                    None => format!("[{}:{}]", part(start), part(end)),
+                }
+            }
+            PathChunk::Argument(index) => format!("({})", index),
+            PathChunk::Wildcard => "*".to_owned(),
+            PathChunk::IndexWildcard => "[*]".to_owned(),
+            PathChunk::Descendants => "**".to_owned(),
+            PathChunk::Key(key) => {
+                let needs_quotes = key.is_empty()
+                    || key == "*"
+                    || key == "**"
+                    || key.contains('.')
+                    || key.starts_with(['\'', '[', '(', '/'])
+                    || key.ends_with([']', ')'])
+                    || relative_json_pointer(key).is_some();
+
+                if needs_quotes {
+                    format!("'{}'", key.replace('\\', "\\\\").replace('\'', "\\'"))
+                } else {
+                    key.to_owned()
+                }
+            }
+        })
+        .collect::<Vec<String>>()
+        .join(".")
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -85,4 +342,136 @@ mod tests {
 
         assert_eq!(vec![PathChunk::Key("Bar".to_owned())], new_abs_path)
     }
+
+    #[test]
+    fn quoted_keys() {
+        assert_eq!(
+            ref_chain("'api.example.com'.port".to_owned()),
+            vec![
+                PathChunk::Key("api.example.com".to_owned()),
+                PathChunk::Key("port".to_owned())
+            ]
+        );
+        assert_eq!(
+            ref_chain("..'[weird]'.'it\\'s'".to_owned()),
+            vec![
+                PathChunk::Parent,
+                PathChunk::Parent,
+                PathChunk::Key("[weird]".to_owned()),
+                PathChunk::Key("it's".to_owned())
+            ]
+        );
+        for key in ["/x", "1#", "2/x"] {
+            let path = vec![PathChunk::Key(key.to_owned())];
+            assert_eq!(format_path(&path), format!("'{}'", key));
+            assert_eq!(ref_chain(format_path(&path)), path);
+        }
+    }
+
+    #[test]
+    fn format_round_trip() {
+        for path in [
+            vec![PathChunk::Parent],
+            vec![PathChunk::Parent, PathChunk::Parent],
+            vec![
+                PathChunk::Key("foo".to_owned()),
+                PathChunk::Index(2),
+                PathChunk::Argument(1),
+            ],
+            vec![
+                PathChunk::Parent,
+                PathChunk::Parent,
+                PathChunk::Key("bar".to_owned()),
+                PathChunk::Parent,
+            ],
+            vec![
+                PathChunk::IndexFromEnd(1),
+                PathChunk::Slice {
+                    start: Some(1),
+                    end: Some(-1),
+                    step: None,
+                },
+                PathChunk::Slice {
+                    start: None,
+                    end: None,
+                    step: Some(-2),
+                },
+            ],
+            vec![
+                PathChunk::Descendants,
+                PathChunk::Key("services".to_owned()),
+                PathChunk::Wildcard,
+                PathChunk::IndexWildcard,
+                PathChunk::Key("*".to_owned()),
+                PathChunk::Key("**".to_owned()),
+            ],
+            vec![
+                PathChunk::Key("api.example.com".to_owned()),
+                PathChunk::Key("[weird]".to_owned()),
+                PathChunk::Key("(odd)".to_owned()),
+                PathChunk::Key("'quoted'".to_owned()),
+                PathChunk::Key("back\\slash".to_owned()),
+                PathChunk::Key("".to_owned()),
+            ],
+        ] {
+            assert_eq!(ref_chain(format_path(&path)), path);
+        }
+    }
+
+    #[test]
+    fn json_pointers() {
+        assert_eq!(
+            ref_chain("/a~1b/m~0n/0".to_owned()),
+            vec![
+                PathChunk::Key("a/b".to_owned()),
+                PathChunk::Key("m~n".to_owned()),
+                PathChunk::Key("0".to_owned())
+            ]
+        );
+        assert_eq!(
+            ref_chain("1/sibling".to_owned()),
+            vec![
+                PathChunk::Parent,
+                PathChunk::Parent,
+                PathChunk::Key("sibling".to_owned())
+            ]
+        );
+        assert_eq!(ref_chain("0#".to_owned()), vec![PathChunk::Parent]);
+        // Plain numbers are still keys
+        assert_eq!(
+            ref_chain("1".to_owned()),
+            vec![PathChunk::Key("1".to_owned())]
+        );
+        assert_eq!(relative_json_pointer("01/foo"), None);
+        assert_eq!(array_index("01"), None);
+        assert_eq!(array_index("10"), Some(10));
+    }
+
+    #[test]
+    fn slices() {
+        assert_eq!(
+            ref_chain("[-1]".to_owned()),
+            vec![PathChunk::IndexFromEnd(1)]
+        );
+        assert_eq!(
+            ref_chain("[::2]".to_owned()),
+            vec![PathChunk::Slice {
+                start: None,
+                end: None,
+                step: Some(2)
+            }]
+        );
+
+        assert_eq!(slice_indices(5, Some(1), Some(3), None), vec![1, 2]);
+        assert_eq!(slice_indices(5, None, None, Some(2)), vec![0, 2, 4]);
+        assert_eq!(slice_indices(5, Some(-2), None, None), vec![3, 4]);
+        assert_eq!(slice_indices(5, None, Some(100), None), vec![0, 1, 2, 3, 4]);
+        assert_eq!(slice_indices(5, None, None, Some(-1)), vec![4, 3, 2, 1, 0]);
+        assert_eq!(slice_indices(5, Some(3), Some(0), Some(-2)), vec![3, 1]);
+        assert_eq!(
+            slice_indices(5, Some(3), Some(1), None),
+            Vec::<usize>::new()
+        );
+        assert_eq!(slice_indices(0, None, None, Some(-1)), Vec::<usize>::new());
+    }
 }
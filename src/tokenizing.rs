@@ -1,3 +1,7 @@
+use bigdecimal::num_bigint::BigInt;
+
+use crate::paths::relative_json_pointer;
+
 #[derive(Debug, PartialEq, Clone)]
 pub enum Token {
     Colon,
@@ -7,13 +11,26 @@ pub enum Token {
     CloseBrace,
     OpenBracket,
     CloseBracket,
-    Text(String),  // Quoted string
-    Ident(String), // Unquoted alphabetic text
-    Int(i64),      // Whole numbers, can be negative
-    Float(f64),    // Floating point numbers, can be negative
+    Text(String),   // Quoted string
+    Ident(String),  // Unquoted alphabetic text
+    Int(i64),       // Whole numbers, can be negative
+    Float(f64),     // Floating point numbers, can be negative
+    BigInt(BigInt), // Whole numbers too large for an i64
 }
 
 pub fn tokenize(input: Vec<u8>) -> impl Iterator<Item = Token> {
+    expand_interpolations(raw_tokens(input, handle_escape_characters)).into_iter()
+}
+
+pub(crate) fn tokenize_json(input: &str) -> impl Iterator<Item = Token> {
+    // Plain json, which has already been validated, with json string escapes
+    raw_tokens(input.into(), |content| {
+        serde_json::from_str(&format!("\"{}\"", content)).unwrap()
+    })
+    .into_iter()
+}
+
+fn raw_tokens(input: Vec<u8>, unescape: fn(String) -> String) -> Vec<Token> {
     let mut stream = str::from_utf8(&input).unwrap().chars().peekable();
     let mut tokens = vec![];
     let mut prev_token_complete = true;
@@ -33,7 +50,7 @@ pub fn tokenize(input: Vec<u8>) -> impl Iterator<Item = Token> {
                         let count = content.chars().rev().take_while(|ch| *ch == '\\').count();
                         if count % 2 == 0 {
                             prev_token_complete = true;
-                            *content = handle_escape_characters(content.clone());
+                            *content = unescape(content.clone());
                         } else {
                             content.push(next_char);
                         }
@@ -124,7 +141,94 @@ pub fn tokenize(input: Vec<u8>) -> impl Iterator<Item = Token> {
             }
         }
     }
-    tokens.into_iter()
+    tokens
+}
+
+fn expand_interpolations(tokens: Vec<Token>) -> Vec<Token> {
+    // Strings with ${ref:path} in them become (merge "text" (str (ref "path")) "text")
+    let mut out = vec![];
+    let mut stream = tokens.into_iter().peekable();
+
+    while let Some(token) = stream.next() {
+        match token {
+            // Object keys are left alone, they can't be dynamic
+            Token::Text(text)
+                if text.contains(INTERPOLATION) && stream.peek() != Some(&Token::Colon) =>
+            {
+                out.extend(interpolate(text))
+            }
+            other => out.push(other),
+        }
+    }
+
+    out
+}
+
+// Other ${...} are left as is, they are common in plain json
+const INTERPOLATION: &str = "${ref:";
+
+fn interpolate(text: String) -> Vec<Token> {
+    let mut parts = vec![];
+    let mut literal = String::new();
+    let mut rest = text.as_str();
+
+    while let Some(start) = rest.find(INTERPOLATION) {
+        if let Some(before) = rest[..start].strip_suffix('$') {
+            // $${ref: is an escaped ${ref:
+            literal.push_str(before);
+            literal.push_str(INTERPOLATION);
+            rest = &rest[start + INTERPOLATION.len()..];
+            continue;
+        }
+
+        literal.push_str(&rest[..start]);
+        let Some(end) = rest[start..].find('}').map(|end| start + end) else {
+            panic!("Unterminated interpolation in '{}'", text);
+        };
+        let path = &rest[start + INTERPOLATION.len()..end];
+        rest = &rest[end + 1..];
+
+        if !literal.is_empty() {
+            parts.push(Token::Text(std::mem::take(&mut literal)));
+        }
+        parts.extend([
+            Token::OpenParanthesis,
+            Token::Ident("str".into()),
+            Token::OpenParanthesis,
+            Token::Ident("ref".into()),
+            Token::Text(interpolated_path(path)),
+            Token::CloseParanthesis,
+            Token::CloseParanthesis,
+        ]);
+    }
+    literal.push_str(rest);
+
+    if parts.is_empty() {
+        // Only escapes, nothing to interpolate
+        return vec![Token::Text(literal)];
+    }
+
+    if !literal.is_empty() {
+        parts.push(Token::Text(literal));
+    }
+
+    let mut out = vec![Token::OpenParanthesis, Token::Ident("merge".into())];
+    out.extend(parts);
+    out.push(Token::CloseParanthesis);
+    out
+}
+
+fn interpolated_path(path: &str) -> String {
+    // Relative paths should be relative to the string, not the ref that is two calls deep
+    if path.starts_with('.') {
+        return format!("..{}", path);
+    }
+
+    if let Some((levels, pointer)) = relative_json_pointer(path) {
+        return format!("{}{}", levels + 2, pointer);
+    }
+
+    path.to_owned()
 }
 
 fn complete_ident(content: &str) -> Token {
@@ -132,19 +236,38 @@ fn complete_ident(content: &str) -> Token {
         return Token::Int(integer);
     }
 
-    // Rust default float parsing is very good, but panics on fractional exponents
+    let digits = content.strip_prefix(['-', '+']).unwrap_or(content);
+    if !digits.is_empty() && digits.chars().all(|ch| ch.is_ascii_digit()) {
+        return Token::BigInt(content.parse().unwrap());
+    }
+
     let lower = content.to_ascii_lowercase();
     let numeric_char = |ch: char| "-+.e".contains(ch) || ch.is_ascii_digit();
     if lower.chars().all(numeric_char) {
-        let (mant, exp) = lower.split_once('e').unwrap_or((&lower, "0"));
-        let mantissa: f64 = mant.parse().unwrap();
-        let exponent: f64 = exp.parse().unwrap();
-        return Token::Float(mantissa * 10.0f64.powf(exponent));
+        // Rust float parsing is correctly rounded, but rejects fractional exponents
+        if let Ok(float) = lower.parse() {
+            return Token::Float(float);
+        }
+
+        if let Some(float) = fractional_exponent(&lower) {
+            return Token::Float(float);
+        }
     }
 
     Token::Ident(content.into())
 }
 
+// Exponents with a decimal point like 1.2e1.2 are not in the json spec, but are
+// supported as an extension. These can't be correctly rounded the same way.
+fn fractional_exponent(content: &str) -> Option<f64> {
+    let (mant, exp) = content
+        .split_once('e')
+        .filter(|(_, exp)| exp.contains('.'))?;
+    let mantissa: f64 = mant.parse().ok()?;
+    let exponent: f64 = exp.parse().ok()?;
+    Some(mantissa * 10.0f64.powf(exponent))
+}
+
 fn is_valid_ident_char(test_char: char) -> bool {
     if test_char.is_ascii_whitespace() {
         return false;
@@ -228,11 +351,56 @@ mod tests {
             ("123.5", Token::Float(123.5)),
             ("-123.5", Token::Float(-123.5)),
             ("-123.5", Token::Float(-123.5)),
+            (
+                "123456789012345678901234567890",
+                Token::BigInt("123456789012345678901234567890".parse().unwrap()),
+            ),
         ] {
             assert_eq!(tokenize(input.bytes().collect()).next().unwrap(), output);
         }
     }
 
+    #[test]
+    fn correctly_rounded_floats() {
+        // Computing these as mantissa * 10^exponent is off by a bit
+        for input in [
+            "1e23",
+            "8.41e21",
+            "1.1e-5",
+            "4.35e-7",
+            "6.02214076E23",
+            "5e-324",
+        ] {
+            assert_eq!(
+                tokenize(input.bytes().collect()).next().unwrap(),
+                Token::Float(input.parse().unwrap())
+            );
+        }
+    }
+
+    #[test]
+    fn fractional_exponents() {
+        for (input, output) in [
+            ("1.2e1.2", 1.2 * 10.0f64.powf(1.2)),
+            ("-1.2E+1.2", -1.2 * 10.0f64.powf(1.2)),
+            ("1.2e-1.2", 1.2 * 10.0f64.powf(-1.2)),
+            ("1e0.5", 10.0f64.powf(0.5)),
+        ] {
+            assert_eq!(
+                tokenize(input.bytes().collect()).next().unwrap(),
+                Token::Float(output)
+            );
+        }
+
+        // Not numbers at all, so they stay identifiers
+        for input in ["-", "e", "1e2e3", "1.2.3"] {
+            assert_eq!(
+                tokenize(input.bytes().collect()).next().unwrap(),
+                Token::Ident(input.into())
+            );
+        }
+    }
+
     #[test]
     fn escape_sequences() {
         for (input, output) in [
@@ -261,4 +429,53 @@ mod tests {
             );
         }
     }
+
+    #[test]
+    fn interpolation() {
+        let tokens = |input: &str| tokenize(input.bytes().collect()).collect::<Vec<_>>();
+
+        assert_eq!(
+            tokens("\"${ref:host}:${ref:..port}\""),
+            vec![
+                Token::OpenParanthesis,
+                Token::Ident("merge".into()),
+                Token::OpenParanthesis,
+                Token::Ident("str".into()),
+                Token::OpenParanthesis,
+                Token::Ident("ref".into()),
+                Token::Text("host".into()),
+                Token::CloseParanthesis,
+                Token::CloseParanthesis,
+                Token::Text(":".into()),
+                Token::OpenParanthesis,
+                Token::Ident("str".into()),
+                Token::OpenParanthesis,
+                Token::Ident("ref".into()),
+                Token::Text("....port".into()),
+                Token::CloseParanthesis,
+                Token::CloseParanthesis,
+                Token::CloseParanthesis,
+            ]
+        );
+        assert_eq!(
+            tokens("\"$${ref:x}\""),
+            vec![Token::Text("${ref:x}".into())]
+        );
+        for other in ["${HOME}/x", "$${HOME}", "cost: $5 {}", "${ref"] {
+            assert_eq!(
+                tokens(&format!("\"{}\"", other)),
+                vec![Token::Text(other.into())]
+            );
+        }
+        assert_eq!(
+            tokens("{\"${ref:x}\": 1}"),
+            vec![
+                Token::OpenBrace,
+                Token::Text("${ref:x}".into()),
+                Token::Colon,
+                Token::Int(1),
+                Token::CloseBrace,
+            ]
+        );
+    }
 }